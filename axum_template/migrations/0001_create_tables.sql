@@ -0,0 +1,23 @@
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    role TEXT NOT NULL
+);
+
+-- AUTOINCREMENT keeps ids of deleted tasks from being handed out again
+CREATE TABLE tasks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    completed BOOLEAN NOT NULL,
+    owner_id INTEGER NOT NULL
+);
+
+CREATE INDEX tasks_owner_id ON tasks (owner_id);
+
+CREATE TABLE sessions (
+    token_hash TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,208 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::db::{Role, Session, Task};
+use crate::error::AppError;
+use crate::store::Store;
+use crate::AppState;
+
+/// How long a session token is valid before it has to be refreshed.
+pub const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A session token, returned by login and refresh.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SessionToken {
+    /// Sent back as `Authorization: Bearer <token>`
+    pub token: String,
+    /// Unix timestamp in seconds
+    pub expires_at: u64,
+}
+
+/// The user a request is authenticated as. Extracting it rejects requests
+/// without a valid `Authorization: Bearer` session token with a 401.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: u64,
+    pub role: Role,
+    token_hash: String,
+}
+
+impl AuthUser {
+    /// Owners can do anything with their tasks. Admins can also read the
+    /// tasks of other users but not change them.
+    pub fn can_read(&self, task: &Task) -> bool {
+        task.owner_id == self.user_id || self.role == Role::Admin
+    }
+
+    pub fn can_write(&self, task: &Task) -> bool {
+        task.owner_id == self.user_id
+    }
+
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        if self.role != Role::Admin {
+            return Err(AppError::Forbidden("Admin role required".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Starts a session for the user and returns its token. Only the token's
+/// hash is stored, so a leaked database does not leak sessions.
+pub async fn start_session(db: &dyn Store, user_id: u64) -> Result<SessionToken, anyhow::Error> {
+    let mut token_bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut token_bytes);
+    let token = hex::encode(token_bytes);
+    let now = unix_now();
+    let expires_at = now + SESSION_TTL.as_secs();
+    db.insert_session(
+        hash_token(&token),
+        Session {
+            user_id,
+            expires_at,
+        },
+        now,
+    )
+    .await?;
+    Ok(SessionToken { token, expires_at })
+}
+
+/// Revokes the session the user authenticated with.
+pub async fn end_session(db: &dyn Store, auth_user: &AuthUser) -> Result<(), anyhow::Error> {
+    db.delete_session(&auth_user.token_hash).await
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("Missing bearer token"))?;
+        // Sessions are looked up by hash, so lookup timing reveals nothing
+        // about stored tokens
+        let token_hash = hash_token(token.trim());
+
+        let session = app_state
+            .db
+            .get_session(&token_hash)
+            .await?
+            .filter(|session| session.expires_at > unix_now())
+            .ok_or_else(|| unauthorized("Invalid or expired session"))?;
+        // Sessions of deleted users are no longer valid
+        let user = app_state
+            .db
+            .get_user(session.user_id)
+            .await?
+            .ok_or_else(|| unauthorized("Invalid or expired session"))?;
+        Ok(AuthUser {
+            user_id: user.id,
+            role: user.role,
+            token_hash,
+        })
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn unauthorized(message: &'static str) -> AppError {
+    AppError::Unauthorized(message.to_string())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::test_support::{app_state, json_body, log_in, send};
+
+    #[tokio::test]
+    async fn task_routes_require_a_session() {
+        let app_state = app_state();
+        let response = send(&app_state, "GET", "/tasks", None, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(&app_state, "GET", "/tasks", Some("not-a-token"), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let token = log_in(&app_state, "alice").await;
+        let response = send(&app_state, "GET", "/tasks", Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn only_the_token_hash_is_stored() {
+        let app_state = app_state();
+        let token = log_in(&app_state, "alice").await;
+        let db = &app_state.db;
+        assert!(db.get_session(&token).await.unwrap().is_none());
+        assert!(db.get_session(&hash_token(&token)).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn logout_revokes_the_session() {
+        let app_state = app_state();
+        let token = log_in(&app_state, "alice").await;
+        let response = send(&app_state, "POST", "/logout", Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = send(&app_state, "GET", "/tasks", Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn refresh_replaces_the_token() {
+        let app_state = app_state();
+        let token = log_in(&app_state, "alice").await;
+        let response = send(&app_state, "POST", "/refresh", Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let refreshed = json_body(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_ne!(refreshed, token);
+
+        let response = send(&app_state, "GET", "/tasks", Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(&app_state, "GET", "/tasks", Some(&refreshed), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_rejected() {
+        let app_state = app_state();
+        let token = log_in(&app_state, "alice").await;
+        let expired = Session {
+            user_id: 1,
+            expires_at: unix_now() - 1,
+        };
+        app_state
+            .db
+            .insert_session(hash_token(&token), expired, 0)
+            .await
+            .unwrap();
+        let response = send(&app_state, "GET", "/tasks", Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,214 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, LINK, LOCATION};
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::request_id::REQUEST_ID;
+use crate::store::StoreBackend;
+
+/// Server settings, read from `HOST`, `PORT`, `CORS_ALLOWED_ORIGINS` (comma
+/// separated, `*` for any), `CORS_ALLOWED_METHODS`, `STORAGE_BACKEND`,
+/// `DATABASE_PATH` and `ADMIN_USERNAME` after `.env` is loaded. Unset ones
+/// keep their [`Config::default`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    /// `None` allows any origin
+    pub allowed_origins: Option<Vec<HeaderValue>>,
+    pub allowed_methods: Vec<Method>,
+    pub backend: StoreBackend,
+    pub database_path: String,
+    /// Registered user made an admin on startup. Everyone who registers is a
+    /// regular user.
+    pub admin_username: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let backend = StoreBackend::default();
+        Config {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            allowed_origins: Some(vec![HeaderValue::from_static("http://127.0.0.1:8080")]),
+            allowed_methods: vec![
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ],
+            backend,
+            database_path: backend.default_path().to_string(),
+            admin_username: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Reads the settings from `var`, falling back to the defaults for the
+    /// variables it has no value for.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, anyhow::Error> {
+        let defaults = Config::default();
+        let port = match var("PORT") {
+            Some(port) => port
+                .parse()
+                .map_err(|e| anyhow::anyhow!("PORT {port} is not a port number: {e}"))?,
+            None => defaults.port,
+        };
+        let allowed_origins = match var("CORS_ALLOWED_ORIGINS") {
+            Some(origins) if origins.trim() == "*" => None,
+            Some(origins) => Some(
+                split_list(&origins)
+                    .map(|origin| {
+                        HeaderValue::from_str(origin)
+                            .map_err(|e| anyhow::anyhow!("Invalid CORS origin {origin}: {e}"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => defaults.allowed_origins,
+        };
+        let allowed_methods = match var("CORS_ALLOWED_METHODS") {
+            Some(methods) => split_list(&methods)
+                .map(|method| {
+                    Method::from_bytes(method.to_uppercase().as_bytes())
+                        .map_err(|e| anyhow::anyhow!("Invalid CORS method {method}: {e}"))
+                })
+                .collect::<Result<_, _>>()?,
+            None => defaults.allowed_methods,
+        };
+        let backend = match var("STORAGE_BACKEND") {
+            Some(backend) => backend.parse()?,
+            None => defaults.backend,
+        };
+        Ok(Config {
+            host: var("HOST").unwrap_or(defaults.host),
+            port,
+            allowed_origins,
+            allowed_methods,
+            backend,
+            database_path: var("DATABASE_PATH")
+                .unwrap_or_else(|| backend.default_path().to_string()),
+            admin_username: var("ADMIN_USERNAME").or(defaults.admin_username),
+        })
+    }
+
+    pub fn bind_address(&self) -> String {
+        // Bracket IPv6 hosts, so `::1` becomes `[::1]:8080`
+        match self.host.parse::<std::net::IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, self.port).to_string(),
+            Err(_) => format!("{}:{}", self.host, self.port),
+        }
+    }
+
+    /// Lets browsers on the allowed origins use the API, including the
+    /// request and response headers of sessions, ETags and paging.
+    pub fn cors(&self) -> CorsLayer {
+        let allow_origin = match &self.allowed_origins {
+            Some(origins) => AllowOrigin::list(origins.clone()),
+            None => AllowOrigin::any(),
+        };
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(self.allowed_methods.clone())
+            .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT, IF_MATCH, REQUEST_ID])
+            .expose_headers([
+                ETAG,
+                LOCATION,
+                LINK,
+                HeaderName::from_static("x-total-count"),
+                REQUEST_ID,
+            ])
+            .max_age(Duration::from_secs(3600))
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::http::header::{
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD,
+        ORIGIN,
+    };
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::test_support::{app_state, send_with_headers};
+
+    fn config(vars: &[(&str, &str)]) -> Result<Config, anyhow::Error> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn unset_variables_use_the_defaults() {
+        assert_eq!(config(&[]).unwrap(), Config::default());
+        assert_eq!(Config::default().bind_address(), "127.0.0.1:8080");
+    }
+
+    #[test]
+    fn variables_override_the_defaults() {
+        let configured = config(&[
+            ("HOST", "::1"),
+            ("PORT", "3000"),
+            (
+                "CORS_ALLOWED_ORIGINS",
+                "https://a.example, https://b.example",
+            ),
+            ("CORS_ALLOWED_METHODS", "get,delete"),
+            ("STORAGE_BACKEND", "sqlite"),
+            ("ADMIN_USERNAME", "alice"),
+        ])
+        .unwrap();
+        assert_eq!(configured.bind_address(), "[::1]:3000");
+        assert_eq!(configured.allowed_origins.unwrap().len(), 2);
+        assert_eq!(configured.allowed_methods, [Method::GET, Method::DELETE]);
+        assert_eq!(configured.database_path, "database.sqlite");
+        assert_eq!(configured.admin_username.as_deref(), Some("alice"));
+
+        let any = config(&[("CORS_ALLOWED_ORIGINS", "*")]).unwrap();
+        assert_eq!(any.allowed_origins, None);
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        assert!(config(&[("PORT", "http")]).is_err());
+        assert!(config(&[("CORS_ALLOWED_METHODS", "GET,(")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn browsers_may_send_every_task_method() {
+        let app_state = app_state();
+        let headers = [
+            (ORIGIN, "http://127.0.0.1:8080"),
+            (ACCESS_CONTROL_REQUEST_METHOD, "DELETE"),
+        ];
+        let response =
+            send_with_headers(&app_state, "OPTIONS", "/task/1", None, &headers, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://127.0.0.1:8080"
+        );
+        let methods = response.headers()[ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("PUT") && methods.contains("DELETE"));
+    }
+}
@@ -1,85 +1,304 @@
 use std::collections::BTreeMap;
-use std::io::Write;
-use std::path::Path;
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
 pub struct Task {
+    /// Assigned by the server when the task is created
+    #[schema(read_only)]
     pub id: u64,
     pub name: String,
     pub description: String,
     pub completed: bool,
+    /// Id of the user the task belongs to, set by the server
+    #[serde(default)]
+    #[schema(read_only)]
+    pub owner_id: u64,
+}
+
+/// Request body of task creation. The id and owner are set by the server.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct CreateTask {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub completed: bool,
+}
+
+/// Request body of task updates.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct UpdateTask {
+    pub name: String,
+    pub description: String,
+    pub completed: bool,
+}
+
+/// Request body of partial task updates. Missing fields are left as they are.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct PatchTask {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub completed: Option<bool>,
+}
+
+impl Task {
+    /// Strong entity tag of the task's current contents, sent as `ETag` and
+    /// compared against `If-Match`.
+    pub fn etag(&self) -> String {
+        let json = serde_json::to_vec(self).expect("tasks always serialize");
+        format!("\"{}\"", hex::encode(&Sha256::digest(json)[..16]))
+    }
+
+    /// The task with its fields replaced by `update`.
+    pub fn updated(&self, update: UpdateTask) -> Task {
+        Task {
+            name: update.name,
+            description: update.description,
+            completed: update.completed,
+            ..self.clone()
+        }
+    }
+
+    /// The task with the fields present in `patch` replaced.
+    pub fn patched(&self, patch: PatchTask) -> Task {
+        Task {
+            name: patch.name.unwrap_or_else(|| self.name.clone()),
+            description: patch
+                .description
+                .unwrap_or_else(|| self.description.clone()),
+            completed: patch.completed.unwrap_or(self.completed),
+            ..self.clone()
+        }
+    }
+}
+
+/// Most tasks returned in one page, and the default page size.
+pub const MAX_PAGE_SIZE: u64 = 100;
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+
+/// Task field to sort by.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskSort {
+    #[default]
+    Id,
+    Name,
+    Completed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query parameters of task listings. Ties are broken by id, so pages are
+/// stable.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TaskQuery {
+    /// Only tasks that are, or are not, completed
+    pub completed: Option<bool>,
+    /// Only tasks whose name or description contains this, ignoring case
+    pub q: Option<String>,
+    #[serde(default)]
+    #[param(inline)]
+    pub sort: TaskSort,
+    #[serde(default)]
+    #[param(inline)]
+    pub order: SortOrder,
+    /// Number of matching tasks to skip
+    #[serde(default)]
+    pub offset: u64,
+    /// Number of tasks to return, at most 100, 50 by default
+    pub limit: Option<u64>,
+}
+
+impl TaskQuery {
+    pub fn limit(&self) -> u64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE)
+    }
+
+    /// Whether `task` passes the `completed` and `q` filters.
+    pub fn matches(&self, task: &Task) -> bool {
+        if self
+            .completed
+            .is_some_and(|completed| task.completed != completed)
+        {
+            return false;
+        }
+        let Some(q) = &self.q else {
+            return true;
+        };
+        let q = q.to_lowercase();
+        task.name.to_lowercase().contains(&q) || task.description.to_lowercase().contains(&q)
+    }
+}
+
+/// One page of a task listing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskPage {
+    pub tasks: Vec<Task>,
+    /// Number of tasks matching the filters across all pages
+    pub total: u64,
 }
 
+/// What a user is allowed to do. Admins can read every user's tasks.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+/// A stored user. Never sent in responses, see [`PublicUser`].
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     pub id: u64,
+    pub username: String,
+    /// Argon2id PHC string, see [`crate::password`]
+    pub password_hash: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// What the API returns about a user.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct PublicUser {
+    pub id: u64,
+    pub username: String,
+    pub role: Role,
+}
+
+impl From<&User> for PublicUser {
+    fn from(user: &User) -> Self {
+        PublicUser {
+            id: user.id,
+            username: user.username.clone(),
+            role: user.role,
+        }
+    }
+}
+
+/// Request body of registration and login.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Credentials {
     pub username: String,
     pub password: String,
 }
 
+/// A logged in session, stored under the SHA-256 of its token.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    pub user_id: u64,
+    /// Unix timestamp in seconds
+    pub expires_at: u64,
+}
+
+/// Every task, user and session, kept in memory. See [`crate::store`] for
+/// how it is stored.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Database {
     pub tasks: BTreeMap<u64, Task>,
     pub users: BTreeMap<u64, User>,
+    #[serde(default)]
+    pub sessions: BTreeMap<String, Session>,
+    /// Id of the next created task. Ids of deleted tasks are never reused.
+    #[serde(default)]
+    pub next_task_id: u64,
 }
 
 impl Database {
     pub fn new() -> Self {
-        Database {
-            tasks: BTreeMap::new(),
-            users: BTreeMap::new(),
-        }
+        Database::default()
     }
 
-    pub fn save(&self) -> Result<(), anyhow::Error> {
-        let json = serde_json::to_string(&self)?;
-        let mut file = std::fs::File::create("database.json")?;
-        file.write_all(json.as_bytes())?;
-        Ok(())
+    /// Stores a new task under the next free id and returns it.
+    pub fn insert_task(&mut self, owner_id: u64, task: CreateTask) -> Task {
+        // Databases written before ids were counted start after the last task
+        let last_id = self.tasks.keys().next_back().copied().unwrap_or_default();
+        let id = self.next_task_id.max(last_id + 1);
+        self.next_task_id = id + 1;
+        let task = Task {
+            id,
+            name: task.name,
+            description: task.description,
+            completed: task.completed,
+            owner_id,
+        };
+        self.tasks.insert(id, task.clone());
+        task
     }
 
-    pub fn load_or_create() -> Result<Self, anyhow::Error> {
-        if !Path::new("database.json").exists() {
-            let database = Database::new();
-            database.save()?;
+    /// Replaces `current` with `updated` unless the stored task changed
+    /// since `current` was read. Returns `false` if it did or is gone.
+    pub fn update_task(&mut self, current: &Task, updated: Task) -> bool {
+        match self.tasks.get_mut(&current.id) {
+            Some(stored) if stored == current => {
+                *stored = updated;
+                true
+            }
+            _ => false,
         }
-        let json = std::fs::read_to_string("database.json")?;
-        let database: Self = serde_json::from_str(&json)?;
-        Ok(database)
-    }
-
-    pub fn insert_task(&mut self, task: Task) {
-        self.tasks.insert(task.id, task);
     }
 
-    pub fn update_task(&mut self, id: u64, task: Task) {
-        self.tasks.insert(id, task);
-    }
-
-    pub fn delete_task(&mut self, id: u64) {
-        self.tasks.remove(&id);
+    /// Returns `false` if there was no task with the id.
+    pub fn delete_task(&mut self, id: u64) -> bool {
+        self.tasks.remove(&id).is_some()
     }
 
     pub fn get_task(&self, id: u64) -> Option<Task> {
         self.tasks.get(&id).cloned()
     }
 
-    pub fn get_tasks(&self) -> Vec<Task> {
-        self.tasks.values().cloned().collect()
-    }
-
-    pub fn insert_user(&mut self, user: User) {
-        self.users.insert(user.id, user);
-    }
-
-    pub fn update_user(&mut self, user: User) {
-        self.users.insert(user.id, user);
+    /// The page of tasks matching `query`, only those of `owner_id` if set.
+    pub fn query_tasks(&self, owner_id: Option<u64>, query: &TaskQuery) -> TaskPage {
+        let mut tasks: Vec<&Task> = self
+            .tasks
+            .values()
+            .filter(|task| owner_id.is_none_or(|owner_id| task.owner_id == owner_id))
+            .filter(|task| query.matches(task))
+            .collect();
+        // Tasks are already in id order and the sort is stable
+        match query.sort {
+            TaskSort::Id => {}
+            TaskSort::Name => tasks.sort_by(|a, b| a.name.cmp(&b.name)),
+            TaskSort::Completed => tasks.sort_by_key(|task| task.completed),
+        }
+        if query.order == SortOrder::Desc {
+            // Reversing also reverses the id tie-breaks, like `DESC` in SQL
+            tasks.reverse();
+        }
+        let total = tasks.len() as u64;
+        let tasks = tasks
+            .into_iter()
+            .skip(query.offset.try_into().unwrap_or(usize::MAX))
+            .take(query.limit().try_into().unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        TaskPage { tasks, total }
     }
 
-    pub fn delete_user(&mut self, id: u64) {
-        self.users.remove(&id);
+    /// Adds a user with the next free id and the [`Role::User`] role.
+    /// Returns `None` if the username is taken.
+    pub fn insert_user(&mut self, username: String, password_hash: String) -> Option<User> {
+        if self.get_user_by_username(&username).is_some() {
+            return None;
+        }
+        let user = User {
+            id: self.users.keys().next_back().map_or(1, |id| id + 1),
+            username,
+            password_hash,
+            role: Role::User,
+        };
+        self.users.insert(user.id, user.clone());
+        Some(user)
     }
 
     pub fn get_user(&self, id: u64) -> Option<User> {
@@ -93,7 +312,32 @@ impl Database {
             .cloned()
     }
 
-    pub fn get_users(&self) -> Vec<User> {
-        self.users.values().cloned().collect()
+    /// Returns `false` if there is no user named `username`.
+    pub fn set_role(&mut self, username: &str, role: Role) -> bool {
+        match self
+            .users
+            .values_mut()
+            .find(|user| user.username == username)
+        {
+            Some(user) => {
+                user.role = role;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Also drops the sessions that expired by `now`.
+    pub fn insert_session(&mut self, token_hash: String, session: Session, now: u64) {
+        self.sessions.retain(|_, session| session.expires_at > now);
+        self.sessions.insert(token_hash, session);
+    }
+
+    pub fn get_session(&self, token_hash: &str) -> Option<Session> {
+        self.sessions.get(token_hash).cloned()
+    }
+
+    pub fn delete_session(&mut self, token_hash: &str) {
+        self.sessions.remove(token_hash);
     }
 }
@@ -1,19 +1,110 @@
 use std::fmt;
 
+use axum::http::header::CONTENT_TYPE;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use serde_json::json;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-pub struct AppError {
-    pub error: anyhow::Error,
-    pub status: StatusCode,
+use crate::request_id;
+use crate::validation::FieldError;
+
+/// Everything a handler can fail with. Sent as an RFC 7807
+/// `application/problem+json` [`Problem`].
+#[derive(Debug)]
+pub enum AppError {
+    /// The request could not be read, e.g. malformed JSON or a path
+    /// parameter of the wrong type
+    Rejected {
+        status: StatusCode,
+        message: String,
+    },
+    /// The request was read but some fields are invalid
+    Validation(Vec<FieldError>),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    PreconditionFailed(String),
+    /// Anything unexpected. Logged with its details, but clients only get a
+    /// generic message so internals do not leak.
+    Internal(anyhow::Error),
+}
+
+/// Body of every error response, see RFC 7807.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Problem {
+    /// Always `about:blank`, `code` tells problems apart
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Reason phrase of the status
+    pub title: String,
+    pub status: u16,
+    /// What went wrong, safe to show to users
+    pub detail: String,
+    /// Stable, machine-readable kind of the problem, e.g. `not_found`
+    pub code: String,
+    /// The invalid fields of `validation_failed` problems
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<FieldError>,
+    /// Also sent as `x-request-id`, for finding the request in the logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl AppError {
-    pub fn new(status: StatusCode, error: impl Into<anyhow::Error>) -> Self {
-        Self {
-            error: error.into(),
-            status,
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Rejected { status, .. } => *status,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Rejected { .. } => "invalid_request",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The problem sent to the client.
+    pub fn problem(&self) -> Problem {
+        let status = self.status();
+        let detail = match self {
+            AppError::Rejected { message, .. }
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::PreconditionFailed(message) => message.clone(),
+            AppError::Validation(_) => "Invalid request".to_string(),
+            AppError::Internal(_) => "Internal server error".to_string(),
+        };
+        let details = match self {
+            AppError::Validation(details) => details.clone(),
+            _ => Vec::new(),
+        };
+        Problem {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail,
+            code: self.code().to_string(),
+            details,
+            request_id: request_id::current(),
         }
     }
 }
@@ -21,18 +112,25 @@ impl AppError {
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (self.status, format!("Something went wrong: {}", self.error)).into_response()
+        let problem = self.problem();
+        if let AppError::Internal(error) = &self {
+            tracing::error!(request_id = ?problem.request_id, "Internal error: {error:#}");
+        }
+        (
+            self.status(),
+            [(CONTENT_TYPE, "application/problem+json")],
+            Json(problem),
+        )
+            .into_response()
     }
 }
 
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let error_json = json!({
-            "error": self.error.to_string(),
-            "status": self.status.as_u16(),
-        });
-
-        write!(f, "{}", error_json)
+        match self {
+            AppError::Internal(error) => write!(f, "{error:#}"),
+            _ => write!(f, "{}", self.problem().detail),
+        }
     }
 }
 
@@ -41,9 +139,62 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self {
-            error: err.into(),
-            status: StatusCode::INTERNAL_SERVER_ERROR, // default status code
-        }
+        AppError::Internal(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, json_body, log_in, send};
+
+    #[tokio::test]
+    async fn internal_errors_are_not_leaked() {
+        let error = AppError::from(anyhow::anyhow!("disk /var/secret is full"));
+        assert!(error.to_string().contains("/var/secret"));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let problem = json_body(response).await;
+        assert_eq!(problem["code"], "internal_error");
+        assert_eq!(problem["detail"], "Internal server error");
+    }
+
+    #[tokio::test]
+    async fn errors_are_problems_with_the_request_id() {
+        let app_state = app_state();
+        let response = send(&app_state, "GET", "/tasks", None, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+        let request_id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let problem: Problem = serde_json::from_value(json_body(response).await).unwrap();
+        assert_eq!(
+            problem,
+            Problem {
+                problem_type: "about:blank".to_string(),
+                title: "Unauthorized".to_string(),
+                status: 401,
+                detail: "Missing bearer token".to_string(),
+                code: "unauthorized".to_string(),
+                details: Vec::new(),
+                request_id: Some(request_id),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn unreadable_requests_are_problems() {
+        let app_state = app_state();
+        let token = log_in(&app_state, "alice").await;
+        let response = send(&app_state, "GET", "/task/x", Some(&token), None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["code"], "invalid_request");
+
+        let missing_field = serde_json::json!({ "username": "alice" });
+        let response = send(&app_state, "POST", "/register", None, Some(missing_field)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json_body(response).await["code"], "invalid_request");
     }
 }
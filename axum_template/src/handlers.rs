@@ -1,101 +1,767 @@
-use axum::extract::{Path, State};
+use axum::extract::State;
+use axum::http::header::{ETAG, IF_MATCH, LINK, LOCATION};
+use axum::http::{HeaderMap, HeaderName, StatusCode, Uri};
 use axum::Json;
-use reqwest::StatusCode;
 
-use crate::db::{Task, User};
-use crate::error::AppError;
+use crate::auth::{self, AuthUser, SessionToken};
+use crate::db::{
+    CreateTask, Credentials, PatchTask, PublicUser, Task, TaskPage, TaskQuery, UpdateTask,
+};
+use crate::error::{AppError, Problem};
+use crate::extract::{AppJson, AppPath, AppQuery};
+use crate::password;
+use crate::validation::validate;
 use crate::AppState;
 
+/// A task response with the task's `ETag` header.
+type TaggedTask = ([(HeaderName, String); 1], Json<Task>);
+
+#[utoipa::path(
+    post,
+    path = "/task",
+    security(("bearer" = [])),
+    request_body = CreateTask,
+    responses(
+        (status = 201, description = "Task created with a new id, owned by the caller", body = Task,
+            headers(
+                ("Location" = String, description = "Path of the new task"),
+                ("ETag" = String, description = "Entity tag of the new task"),
+            )
+        ),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid task fields", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Internal error", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 #[axum::debug_handler]
 pub async fn create_task(
     State(app_state): State<AppState>,
-    Json(task): Json<Task>,
-) -> Result<Json<Task>, AppError> {
-    let mut db = app_state.db.lock().await;
-    db.insert_task(task.clone());
-
-    // return a 200
-    Ok(Json(task))
+    auth_user: AuthUser,
+    AppJson(task): AppJson<CreateTask>,
+) -> Result<(StatusCode, [(HeaderName, String); 2], Json<Task>), AppError> {
+    validate(&task)?;
+    let task = app_state.db.insert_task(auth_user.user_id, task).await?;
+    let headers = [
+        (LOCATION, format!("/task/{}", task.id)),
+        (ETAG, task.etag()),
+    ];
+    Ok((StatusCode::CREATED, headers, Json(task)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/task/{id}",
+    security(("bearer" = [])),
+    params(("id" = u64, Path, description = "Task id")),
+    responses(
+        (status = 200, description = "Task found", body = Task,
+            headers(("ETag" = String, description = "Entity tag to send as If-Match when updating"))
+        ),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Task belongs to another user", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Task not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 #[axum::debug_handler]
 pub async fn read_task(
     State(app_state): State<AppState>,
-    Path(id): Path<u64>,
-) -> Result<Json<Task>, AppError> {
-    let db = app_state.db.lock().await;
-    match db.get_task(id) {
-        Some(task) => Ok(Json(task.clone())),
-        None => Err(AppError::new(
-            StatusCode::NOT_FOUND,
-            anyhow::anyhow!("Task not found"),
-        )),
+    auth_user: AuthUser,
+    AppPath(id): AppPath<u64>,
+) -> Result<TaggedTask, AppError> {
+    match app_state.db.get_task(id).await? {
+        Some(task) if auth_user.can_read(&task) => Ok(([(ETAG, task.etag())], Json(task))),
+        Some(_) => Err(forbidden()),
+        None => Err(not_found()),
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/tasks",
+    security(("bearer" = [])),
+    params(TaskQuery),
+    responses(
+        (status = 200, description = "A page of the caller's tasks", body = Vec<Task>,
+            headers(
+                ("X-Total-Count" = u64, description = "Number of matching tasks across all pages"),
+                ("Link" = String, description = "URLs of the first, previous, next and last pages"),
+            )
+        ),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid query parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[axum::debug_handler]
+pub async fn read_tasks(
+    State(app_state): State<AppState>,
+    auth_user: AuthUser,
+    uri: Uri,
+    AppQuery(query): AppQuery<TaskQuery>,
+) -> Result<TaskListing, AppError> {
+    validate(&query)?;
+    let page = app_state
+        .db
+        .query_tasks(Some(auth_user.user_id), &query)
+        .await?;
+    task_listing(uri.path(), &query, page)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/tasks",
+    security(("bearer" = [])),
+    params(TaskQuery),
+    responses(
+        (status = 200, description = "A page of every user's tasks", body = Vec<Task>,
+            headers(
+                ("X-Total-Count" = u64, description = "Number of matching tasks across all pages"),
+                ("Link" = String, description = "URLs of the first, previous, next and last pages"),
+            )
+        ),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Caller is not an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid query parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 #[axum::debug_handler]
-pub async fn read_tasks(State(app_state): State<AppState>) -> Result<Json<Vec<Task>>, AppError> {
-    let db = app_state.db.lock().await;
-    let tasks = db.get_tasks();
-    Ok(Json(tasks))
+pub async fn read_all_tasks(
+    State(app_state): State<AppState>,
+    auth_user: AuthUser,
+    uri: Uri,
+    AppQuery(query): AppQuery<TaskQuery>,
+) -> Result<TaskListing, AppError> {
+    auth_user.require_admin()?;
+    validate(&query)?;
+    let page = app_state.db.query_tasks(None, &query).await?;
+    task_listing(uri.path(), &query, page)
+}
+
+/// A page of tasks with its `X-Total-Count` and `Link` headers.
+type TaskListing = ([(HeaderName, String); 2], Json<Vec<Task>>);
+
+fn task_listing(path: &str, query: &TaskQuery, page: TaskPage) -> Result<TaskListing, AppError> {
+    let limit = query.limit();
+    let last = page.total.saturating_sub(1) / limit * limit;
+    let mut links = vec![(0, "first")];
+    if query.offset > 0 {
+        links.push((query.offset.saturating_sub(limit).min(last), "prev"));
+    }
+    if query.offset.saturating_add(limit) < page.total {
+        links.push((query.offset + limit, "next"));
+    }
+    links.push((last, "last"));
+
+    let links = links
+        .into_iter()
+        .map(|(offset, rel)| {
+            let query = serde_urlencoded::to_string(TaskQuery {
+                offset,
+                limit: Some(limit),
+                ..query.clone()
+            })?;
+            Ok(format!("<{path}?{query}>; rel=\"{rel}\""))
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    let headers = [
+        (
+            HeaderName::from_static("x-total-count"),
+            page.total.to_string(),
+        ),
+        (LINK, links.join(", ")),
+    ];
+    Ok((headers, Json(page.tasks)))
 }
 
+#[utoipa::path(
+    put,
+    path = "/task/{id}",
+    security(("bearer" = [])),
+    params(
+        ("id" = u64, Path, description = "Task id"),
+        ("If-Match" = Option<String>, Header, description = "Only update if the task still has this ETag"),
+    ),
+    request_body = UpdateTask,
+    responses(
+        (status = 200, description = "Task replaced", body = Task,
+            headers(("ETag" = String, description = "Entity tag of the updated task"))
+        ),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Task belongs to another user", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Task not found", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Task changed since the If-Match ETag was read", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid task fields", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 #[axum::debug_handler]
 pub async fn update_task(
     State(app_state): State<AppState>,
-    Path(id): Path<u64>,
-    Json(task): Json<Task>,
-) -> Result<Json<Task>, AppError> {
-    let mut db = app_state.db.lock().await;
-    db.update_task(id, task.clone());
-    Ok(Json(task))
+    auth_user: AuthUser,
+    AppPath(id): AppPath<u64>,
+    headers: HeaderMap,
+    AppJson(update): AppJson<UpdateTask>,
+) -> Result<TaggedTask, AppError> {
+    validate(&update)?;
+    change_task(&app_state, &auth_user, id, &headers, |task| {
+        task.updated(update)
+    })
+    .await
 }
 
+#[utoipa::path(
+    patch,
+    path = "/task/{id}",
+    security(("bearer" = [])),
+    params(
+        ("id" = u64, Path, description = "Task id"),
+        ("If-Match" = Option<String>, Header, description = "Only update if the task still has this ETag"),
+    ),
+    request_body = PatchTask,
+    responses(
+        (status = 200, description = "Given fields updated", body = Task,
+            headers(("ETag" = String, description = "Entity tag of the updated task"))
+        ),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Task belongs to another user", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Task not found", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Task changed since the If-Match ETag was read", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid task fields", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[axum::debug_handler]
+pub async fn patch_task(
+    State(app_state): State<AppState>,
+    auth_user: AuthUser,
+    AppPath(id): AppPath<u64>,
+    headers: HeaderMap,
+    AppJson(patch): AppJson<PatchTask>,
+) -> Result<TaggedTask, AppError> {
+    validate(&patch)?;
+    change_task(&app_state, &auth_user, id, &headers, |task| {
+        task.patched(patch)
+    })
+    .await
+}
+
+/// Applies `change` to the caller's task, honouring `If-Match`. The store
+/// only writes if the task is unchanged since it was read, so concurrent
+/// updates without `If-Match` cannot overwrite each other unnoticed either.
+async fn change_task(
+    app_state: &AppState,
+    auth_user: &AuthUser,
+    id: u64,
+    headers: &HeaderMap,
+    change: impl FnOnce(&Task) -> Task,
+) -> Result<TaggedTask, AppError> {
+    let current = app_state.db.get_task(id).await?.ok_or_else(not_found)?;
+    if !auth_user.can_write(&current) {
+        return Err(forbidden());
+    }
+    if !if_match_allows(headers, &current.etag()) {
+        return Err(precondition_failed());
+    }
+    let updated = change(&current);
+    if !app_state.db.update_task(&current, updated.clone()).await? {
+        return Err(match app_state.db.get_task(id).await? {
+            Some(_) => precondition_failed(),
+            None => not_found(),
+        });
+    }
+    Ok(([(ETAG, updated.etag())], Json(updated)))
+}
+
+/// Whether the `If-Match` header, if any, lists `etag` or is `*`. Weak tags
+/// never match, as required for `If-Match`.
+fn if_match_allows(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(if_match) = headers.get(IF_MATCH) else {
+        return true;
+    };
+    let Ok(if_match) = if_match.to_str() else {
+        return false;
+    };
+    if_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/task/{id}",
+    security(("bearer" = [])),
+    params(("id" = u64, Path, description = "Task id")),
+    responses(
+        (status = 204, description = "Task deleted"),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Task belongs to another user", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Task not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 #[axum::debug_handler]
 pub async fn delete_task(
     State(app_state): State<AppState>,
-    Path(id): Path<u64>,
-) -> Result<Json<()>, AppError> {
-    let mut db = app_state.db.lock().await;
-    db.delete_task(id);
-    Ok(Json(()))
+    auth_user: AuthUser,
+    AppPath(id): AppPath<u64>,
+) -> Result<StatusCode, AppError> {
+    let existing = app_state.db.get_task(id).await?.ok_or_else(not_found)?;
+    if !auth_user.can_write(&existing) {
+        return Err(forbidden());
+    }
+    if !app_state.db.delete_task(id).await? {
+        return Err(not_found());
+    }
+    Ok(StatusCode::NO_CONTENT)
 }
 
+fn forbidden() -> AppError {
+    AppError::Forbidden("Task belongs to another user".to_string())
+}
+
+fn not_found() -> AppError {
+    AppError::NotFound("Task not found".to_string())
+}
+
+fn precondition_failed() -> AppError {
+    AppError::PreconditionFailed(
+        "Task was changed, fetch it again for its current ETag".to_string(),
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/register",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "User registered", body = PublicUser),
+        (status = 409, description = "Username already taken", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid username or password", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 #[axum::debug_handler]
 pub async fn create_user(
     State(app_state): State<AppState>,
-    Json(user): Json<User>,
-) -> Result<Json<User>, AppError> {
-    let mut db = app_state.db.lock().await;
-    db.insert_user(user.clone());
+    AppJson(credentials): AppJson<Credentials>,
+) -> Result<Json<PublicUser>, AppError> {
+    validate(&credentials)?;
+    // Hashing is deliberately slow, keep it off the async workers
+    let password = credentials.password;
+    let password_hash =
+        tokio::task::spawn_blocking(move || password::hash_password(&password)).await??;
+
+    let Some(user) = app_state
+        .db
+        .insert_user(credentials.username, password_hash)
+        .await?
+    else {
+        return Err(AppError::Conflict("Username already taken".to_string()));
+    };
 
-    // return a 200
-    Ok(Json(user))
+    Ok(Json(PublicUser::from(&user)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/login",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "Logged in, returns a session token", body = SessionToken),
+        (status = 401, description = "Invalid username or password", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 #[axum::debug_handler]
 pub async fn login(
     State(app_state): State<AppState>,
-    Json(user): Json<User>,
-) -> Result<Json<String>, AppError> {
-    let db = app_state.db.lock().await;
-    let user = db.get_user_by_username(&user.username);
-    match user {
-        Some(user) => {
-            if user.password == user.password {
-                return Ok(Json(user.username));
-            } else {
-                return Err(AppError::new(
-                    StatusCode::UNAUTHORIZED,
-                    anyhow::anyhow!("Invalid username or password"),
-                ));
+    AppJson(credentials): AppJson<Credentials>,
+) -> Result<Json<SessionToken>, AppError> {
+    let stored = app_state
+        .db
+        .get_user_by_username(&credentials.username)
+        .await?;
+    // Unknown usernames are verified against a dummy hash so they take as
+    // long to reject as wrong passwords
+    let password_hash = stored.as_ref().map_or_else(
+        || password::dummy_hash().to_string(),
+        |user| user.password_hash.clone(),
+    );
+    let password = credentials.password;
+    let verified =
+        tokio::task::spawn_blocking(move || password::verify_password(&password, &password_hash))
+            .await??;
+    match stored {
+        Some(user) if verified => Ok(Json(
+            auth::start_session(app_state.db.as_ref(), user.id).await?,
+        )),
+        _ => Err(AppError::Unauthorized(
+            "Invalid username or password".to_string(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/logout",
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Session revoked"),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[axum::debug_handler]
+pub async fn logout(
+    State(app_state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth::end_session(app_state.db.as_ref(), &auth_user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/refresh",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "New session token, the old one is revoked", body = SessionToken),
+        (status = 401, description = "Missing or invalid session token", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+#[axum::debug_handler]
+pub async fn refresh(
+    State(app_state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<SessionToken>, AppError> {
+    auth::end_session(app_state.db.as_ref(), &auth_user).await?;
+    Ok(Json(
+        auth::start_session(app_state.db.as_ref(), auth_user.user_id).await?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::store::JsonStore;
+
+    fn app_state() -> AppState {
+        AppState {
+            db: Arc::new(JsonStore::in_memory()),
+        }
+    }
+
+    fn credentials(username: &str, password: &str) -> AppJson<Credentials> {
+        AppJson(Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    async fn register(app_state: &AppState, username: &str, password: &str) -> PublicUser {
+        let Json(user) = create_user(State(app_state.clone()), credentials(username, password))
+            .await
+            .unwrap();
+        user
+    }
+
+    #[tokio::test]
+    async fn register_stores_a_hash_and_returns_no_password() {
+        let app_state = app_state();
+        let user = register(&app_state, "alice", "hunter2").await;
+        assert_eq!(user.username, "alice");
+
+        let response = serde_json::to_value(&user).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({ "id": 1, "username": "alice", "role": "user" })
+        );
+
+        let stored = app_state.db.get_user(user.id).await.unwrap().unwrap();
+        assert_ne!(stored.password_hash, "hunter2");
+        assert!(password::verify_password("hunter2", &stored.password_hash).unwrap());
+    }
+
+    #[tokio::test]
+    async fn register_rejects_taken_usernames() {
+        let app_state = app_state();
+        register(&app_state, "alice", "hunter2").await;
+        let error = create_user(State(app_state), credentials("alice", "other"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn login_accepts_the_right_password() {
+        let app_state = app_state();
+        register(&app_state, "alice", "hunter2").await;
+        let Json(session) = login(State(app_state), credentials("alice", "hunter2"))
+            .await
+            .unwrap();
+        assert_eq!(session.token.len(), 64);
+    }
+
+    #[tokio::test]
+    async fn login_rejects_wrong_passwords_and_unknown_users() {
+        let app_state = app_state();
+        register(&app_state, "alice", "hunter2").await;
+        for (username, password) in [("alice", "hunter3"), ("alice", ""), ("bob", "hunter2")] {
+            let error = login(State(app_state.clone()), credentials(username, password))
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(error.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    mod ownership {
+        use serde_json::{json, Value};
+
+        use super::*;
+        use crate::db::Role;
+        use crate::test_support::{json_body, log_in, send};
+
+        fn task(id: u64, name: &str) -> Value {
+            json!({ "id": id, "name": name, "description": "", "completed": false })
+        }
+
+        /// An admin and two regular users with a task each.
+        async fn setup() -> (AppState, String, String, String) {
+            let app_state = super::app_state();
+            let admin = log_in(&app_state, "admin").await;
+            assert!(app_state.db.set_role("admin", Role::Admin).await.unwrap());
+            let alice = log_in(&app_state, "alice").await;
+            let bob = log_in(&app_state, "bob").await;
+            send(
+                &app_state,
+                "POST",
+                "/task",
+                Some(&alice),
+                Some(task(1, "alice's")),
+            )
+            .await;
+            send(
+                &app_state,
+                "POST",
+                "/task",
+                Some(&bob),
+                Some(task(2, "bob's")),
+            )
+            .await;
+            (app_state, admin, alice, bob)
+        }
+
+        #[tokio::test]
+        async fn tasks_are_listed_per_owner() {
+            let (app_state, _, alice, _) = setup().await;
+            let response = send(&app_state, "GET", "/tasks", Some(&alice), None).await;
+            let tasks = json_body(response).await;
+            assert_eq!(tasks.as_array().unwrap().len(), 1);
+            assert_eq!(tasks[0]["name"], "alice's");
+            assert_eq!(tasks[0]["owner_id"], 2);
+        }
+
+        #[tokio::test]
+        async fn owners_cannot_be_spoofed() {
+            let (app_state, _, alice, _) = setup().await;
+            let mut spoofed = task(3, "spoofed");
+            spoofed["owner_id"] = json!(3);
+            let response = send(&app_state, "POST", "/task", Some(&alice), Some(spoofed)).await;
+            assert_eq!(json_body(response).await["owner_id"], 2);
+        }
+
+        #[tokio::test]
+        async fn other_users_tasks_are_forbidden() {
+            let (app_state, _, alice, _) = setup().await;
+            for (method, body) in [
+                ("GET", None),
+                ("PUT", Some(task(2, "taken over"))),
+                ("DELETE", None),
+            ] {
+                let response = send(&app_state, method, "/task/2", Some(&alice), body).await;
+                assert_eq!(response.status(), StatusCode::FORBIDDEN, "{method}");
             }
+            let stored = app_state.db.get_task(2).await.unwrap().unwrap();
+            assert_eq!(stored.name, "bob's");
+
+            let response = send(&app_state, "GET", "/task/9", Some(&alice), None).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[tokio::test]
+        async fn ids_are_assigned_by_the_server() {
+            let (app_state, _, alice, _) = setup().await;
+            // A client-provided id neither overwrites bob's task nor is used
+            let response = send(
+                &app_state,
+                "POST",
+                "/task",
+                Some(&alice),
+                Some(task(2, "x")),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            assert_eq!(json_body(response).await["id"], 3);
+            let stored = app_state.db.get_task(2).await.unwrap().unwrap();
+            assert_eq!(stored.name, "bob's");
         }
-        None => {
-            return Err(AppError::new(
-                StatusCode::UNAUTHORIZED,
-                anyhow::anyhow!("Invalid username or password"),
-            ))
+
+        #[tokio::test]
+        async fn invalid_fields_are_listed() {
+            let (app_state, _, alice, _) = setup().await;
+            let response = send(
+                &app_state,
+                "POST",
+                "/task",
+                Some(&alice),
+                Some(task(0, " ")),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let problem = json_body(response).await;
+            assert_eq!(problem["code"], "validation_failed");
+            assert_eq!(
+                problem["details"],
+                json!([{ "field": "name", "message": "must not be empty" }])
+            );
+
+            let credentials = json!({ "username": "", "password": "hunter2" });
+            let response = send(&app_state, "POST", "/register", None, Some(credentials)).await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(json_body(response).await["details"][0]["field"], "username");
+        }
+
+        #[tokio::test]
+        async fn admins_read_every_task_but_cannot_change_them() {
+            let (app_state, admin, alice, _) = setup().await;
+            let response = send(&app_state, "GET", "/admin/tasks", Some(&admin), None).await;
+            assert_eq!(json_body(response).await.as_array().unwrap().len(), 2);
+            let response = send(&app_state, "GET", "/task/2", Some(&admin), None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = send(&app_state, "DELETE", "/task/2", Some(&admin), None).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+            let response = send(&app_state, "GET", "/admin/tasks", Some(&alice), None).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    mod rest {
+        use axum::http::header::{ETAG, IF_MATCH, LINK, LOCATION};
+        use serde_json::json;
+
+        use super::*;
+        use crate::test_support::{json_body, log_in, send, send_with_headers};
+
+        async fn setup() -> (AppState, String) {
+            let app_state = super::app_state();
+            let token = log_in(&app_state, "alice").await;
+            let task = json!({ "name": "write docs", "description": "api" });
+            let response = send(&app_state, "POST", "/task", Some(&token), Some(task)).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            assert_eq!(response.headers()[LOCATION], "/task/1");
+            (app_state, token)
+        }
+
+        #[tokio::test]
+        async fn missing_tasks_are_not_found() {
+            let (app_state, token) = setup().await;
+            let update = json!({ "name": "x", "description": "", "completed": true });
+            for (method, body) in [
+                ("PUT", Some(update)),
+                ("PATCH", Some(json!({}))),
+                ("DELETE", None),
+            ] {
+                let response = send(&app_state, method, "/task/9", Some(&token), body).await;
+                assert_eq!(response.status(), StatusCode::NOT_FOUND, "{method}");
+            }
+            assert!(app_state.db.get_task(9).await.unwrap().is_none());
+        }
+
+        #[tokio::test]
+        async fn patch_only_changes_the_given_fields() {
+            let (app_state, token) = setup().await;
+            let patch = json!({ "completed": true });
+            let response = send(&app_state, "PATCH", "/task/1", Some(&token), Some(patch)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let task = json_body(response).await;
+            assert_eq!(task["name"], "write docs");
+            assert_eq!(task["description"], "api");
+            assert_eq!(task["completed"], true);
+        }
+
+        #[tokio::test]
+        async fn delete_reports_whether_anything_was_deleted() {
+            let (app_state, token) = setup().await;
+            let response = send(&app_state, "DELETE", "/task/1", Some(&token), None).await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            let response = send(&app_state, "DELETE", "/task/1", Some(&token), None).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[tokio::test]
+        async fn listings_are_paged_with_links() {
+            let (app_state, token) = setup().await;
+            for name in ["b", "c"] {
+                let task = json!({ "name": name });
+                send(&app_state, "POST", "/task", Some(&token), Some(task)).await;
+            }
+            let uri = "/tasks?sort=name&order=desc&limit=1&offset=1";
+            let response = send(&app_state, "GET", uri, Some(&token), None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["x-total-count"], "3");
+            let query = |offset| format!("</tasks?sort=name&order=desc&offset={offset}&limit=1>");
+            assert_eq!(
+                response.headers()[LINK].to_str().unwrap(),
+                format!(
+                    "{}; rel=\"first\", {}; rel=\"prev\", {}; rel=\"next\", {}; rel=\"last\"",
+                    query(0),
+                    query(0),
+                    query(2),
+                    query(2)
+                )
+            );
+            assert_eq!(json_body(response).await[0]["name"], "c");
+
+            let uri = "/tasks?completed=false&q=WRITE";
+            let response = send(&app_state, "GET", uri, Some(&token), None).await;
+            assert_eq!(json_body(response).await[0]["name"], "write docs");
+
+            let response = send(&app_state, "GET", "/tasks?limit=0", Some(&token), None).await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        #[tokio::test]
+        async fn stale_etags_are_rejected() {
+            let (app_state, token) = setup().await;
+            let response = send(&app_state, "GET", "/task/1", Some(&token), None).await;
+            let etag = response.headers()[ETAG].to_str().unwrap().to_string();
+
+            let patch = |name: &str| Some(json!({ "name": name }));
+            let response = send_with_headers(
+                &app_state,
+                "PATCH",
+                "/task/1",
+                Some(&token),
+                &[(IF_MATCH, &etag)],
+                patch("first"),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let new_etag = response.headers()[ETAG].to_str().unwrap().to_string();
+            assert_ne!(new_etag, etag);
+
+            // A second writer still holding the old ETag is refused
+            let response = send_with_headers(
+                &app_state,
+                "PATCH",
+                "/task/1",
+                Some(&token),
+                &[(IF_MATCH, &etag)],
+                patch("second"),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+            let stored = app_state.db.get_task(1).await.unwrap().unwrap();
+            assert_eq!(stored.name, "first");
         }
     }
 }
@@ -1,56 +1,113 @@
 use std::sync::Arc;
-use std::time::Duration;
 
-use async_trait::async_trait;
-use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
-use axum::http::HeaderValue;
-use db::Database;
-use reqwest::Client as ReqwestClient;
-use tokio::sync::Mutex;
-use tower_http::cors::CorsLayer;
-use tracing::info;
+use config::Config;
+use db::Role;
+use store::Store;
+use tracing::{info, warn};
 
+mod auth;
+mod config;
 mod db;
 mod error;
+mod extract;
 mod handlers;
+mod openapi;
+mod password;
+mod request_id;
+mod sqlite;
+mod store;
+#[cfg(test)]
+mod test_support;
+mod validation;
 
 #[derive(Debug, Clone)]
 struct AppState {
-    db: Arc<Mutex<Database>>,
+    db: Arc<dyn Store>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     init_logging_and_env()?;
-    let db = Arc::new(Mutex::new(Database::load_or_create()?));
-    let app_state = AppState { db };
-    let cors = CorsLayer::new()
-        .allow_origin(HeaderValue::from_static("http://127.0.0.1:8080"))
-        .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
-        .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT])
-        .max_age(Duration::from_secs(3600));
-
-    let app = axum::Router::new()
+    let config = Config::from_env()?;
+    let db = config.backend.open(&config.database_path).await?;
+    info!(
+        "Using {:?} storage at {}",
+        config.backend, config.database_path
+    );
+    if let Some(admin_username) = &config.admin_username {
+        if db.set_role(admin_username, Role::Admin).await? {
+            info!("{admin_username} is an admin");
+        } else {
+            warn!("ADMIN_USERNAME {admin_username} is not registered, restart once it is");
+        }
+    }
+    let app_state = AppState { db: db.clone() };
+    let app = router(app_state, &config);
+
+    let address = config.bind_address();
+    let listener = tokio::net::TcpListener::bind(&address)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to bind to {address}: {e}"))?;
+    info!("Server listening on http://{address}");
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to start server: {e}"))?;
+
+    // In-flight requests have finished, nothing writes to the store anymore
+    db.flush().await?;
+    info!("Database flushed, shutting down");
+    Ok(())
+}
+
+fn router(app_state: AppState, config: &Config) -> axum::Router {
+    axum::Router::new()
         .route("/task", axum::routing::post(handlers::create_task))
         .route("/tasks", axum::routing::get(handlers::read_tasks))
+        .route("/admin/tasks", axum::routing::get(handlers::read_all_tasks))
         .route("/task/:id", axum::routing::get(handlers::read_task))
         .route("/task/:id", axum::routing::put(handlers::update_task))
+        .route("/task/:id", axum::routing::patch(handlers::patch_task))
         .route("/task/:id", axum::routing::delete(handlers::delete_task))
         .route("/register", axum::routing::post(handlers::create_user))
         .route("/login", axum::routing::post(handlers::login))
-        .layer(cors)
-        .with_state(app_state);
-
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080")
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to bind to port 8080: {e}"))?;
-    info!("Server listening on http://127.0.0.1:8080");
+        .route("/logout", axum::routing::post(handlers::logout))
+        .route("/refresh", axum::routing::post(handlers::refresh))
+        .route("/openapi.json", axum::routing::get(openapi::openapi_json))
+        .layer(config.cors())
+        .layer(axum::middleware::from_fn(request_id::propagate))
+        .with_state(app_state)
+}
 
-    axum::serve(listener, app)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to start server: {e}"))?;
+/// Resolves on Ctrl-C or, on Unix, SIGTERM, so the server stops accepting
+/// connections and drains the open ones.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    Ok(())
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
 }
 
 fn init_logging_and_env() -> Result<(), anyhow::Error> {
@@ -0,0 +1,61 @@
+use axum::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::auth::SessionToken;
+use crate::db::{CreateTask, Credentials, PatchTask, PublicUser, Role, Task, UpdateTask};
+use crate::error::Problem;
+use crate::handlers;
+use crate::validation::FieldError;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Task and user management API"),
+    paths(
+        handlers::create_task,
+        handlers::read_tasks,
+        handlers::read_all_tasks,
+        handlers::read_task,
+        handlers::update_task,
+        handlers::patch_task,
+        handlers::delete_task,
+        handlers::create_user,
+        handlers::login,
+        handlers::logout,
+        handlers::refresh,
+    ),
+    components(schemas(
+        Task,
+        CreateTask,
+        UpdateTask,
+        PatchTask,
+        Role,
+        PublicUser,
+        Credentials,
+        SessionToken,
+        Problem,
+        FieldError
+    )),
+    modifiers(&BearerAuth)
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearer` scheme the protected routes refer to.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+/// Serves the OpenAPI document so agents can call the API from a
+/// machine-readable contract.
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,60 @@
+use std::sync::OnceLock;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+/// Hashes a password with Argon2id and a random salt into a PHC string, which
+/// records the parameters and salt next to the hash.
+pub fn hash_password(password: &str) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+/// Checks a password against a PHC string from [`hash_password`]. The hashes
+/// are compared in constant time.
+pub fn verify_password(password: &str, password_hash: &str) -> Result<bool, anyhow::Error> {
+    let password_hash = PasswordHash::new(password_hash)
+        .map_err(|e| anyhow::anyhow!("Invalid password hash: {e}"))?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &password_hash)
+        .is_ok())
+}
+
+/// A hash no password matches, verified against when the username is
+/// unknown so that login takes as long as for existing users.
+pub fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH
+        .get_or_init(|| hash_password("").expect("hashing never fails with default parameters"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_salted_argon2id() {
+        let first = hash_password("hunter2").unwrap();
+        let second = hash_password("hunter2").unwrap();
+        assert!(first.starts_with("$argon2id$"));
+        assert!(!first.contains("hunter2"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn verifies_only_the_right_password() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(verify_password("hunter2", &hash).unwrap());
+        assert!(!verify_password("hunter3", &hash).unwrap());
+        assert!(!verify_password("", &hash).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        assert!(verify_password("hunter2", "hunter2").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+/// The id of the request being handled, `None` outside of [`propagate`].
+pub fn current() -> Option<String> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Gives every request an id, reusing a well-formed `x-request-id` from the
+/// client. The id is echoed in the response and available to the handler
+/// through [`current`].
+pub async fn propagate(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_well_formed(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            let mut bytes = [0u8; 16];
+            OsRng.fill_bytes(&mut bytes);
+            hex::encode(bytes)
+        });
+    let mut response = CURRENT.scope(request_id.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+    response
+}
+
+/// Client ids end up in logs, so only short, plain ones are accepted.
+fn is_well_formed(id: &str) -> bool {
+    (1..=128).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, send, send_with_headers};
+
+    #[tokio::test]
+    async fn ids_are_generated_or_reused() {
+        let app_state = app_state();
+        let response = send(&app_state, "GET", "/openapi.json", None, None).await;
+        assert_eq!(response.headers()[REQUEST_ID].len(), 32);
+
+        for (sent, reused) in [("trace-1", true), ("bad id; rm -rf", false)] {
+            let headers = [(REQUEST_ID, sent)];
+            let response =
+                send_with_headers(&app_state, "GET", "/openapi.json", None, &headers, None).await;
+            assert_eq!(response.headers()[REQUEST_ID] == sent, reused, "{sent}");
+        }
+    }
+}
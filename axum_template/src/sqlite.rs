@@ -0,0 +1,290 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqliteRow};
+use sqlx::{QueryBuilder, Row, Sqlite};
+
+use crate::db::{CreateTask, Role, Session, SortOrder, Task, TaskPage, TaskQuery, TaskSort, User};
+use crate::store::Store;
+
+/// Stores everything in a SQLite file, migrated to the schema in
+/// `migrations/` when opened. Requests run on a connection pool instead of
+/// queueing behind one lock.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it if it does not exist, and
+    /// applies pending migrations.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| anyhow::anyhow!("Failed to create {}: {e}", dir.display()))?;
+        }
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {e}", path.display()))?;
+        sqlx::migrate!()
+            .run(&pool)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to migrate {}: {e}", path.display()))?;
+        Ok(SqliteStore { pool })
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn insert_task(&self, owner_id: u64, task: CreateTask) -> Result<Task, anyhow::Error> {
+        let row = sqlx::query(
+            "INSERT INTO tasks (name, description, completed, owner_id)
+             VALUES (?, ?, ?, ?)
+             RETURNING *",
+        )
+        .bind(task.name)
+        .bind(task.description)
+        .bind(task.completed)
+        .bind(owner_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        task_from_row(&row)
+    }
+
+    async fn update_task(&self, current: &Task, updated: Task) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query(
+            "UPDATE tasks SET name = ?, description = ?, completed = ?, owner_id = ?
+             WHERE id = ? AND name = ? AND description = ? AND completed = ? AND owner_id = ?",
+        )
+        .bind(updated.name)
+        .bind(updated.description)
+        .bind(updated.completed)
+        .bind(updated.owner_id as i64)
+        .bind(current.id as i64)
+        .bind(&current.name)
+        .bind(&current.description)
+        .bind(current.completed)
+        .bind(current.owner_id as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete_task(&self, id: u64) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query("DELETE FROM tasks WHERE id = ?")
+            .bind(id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn get_task(&self, id: u64) -> Result<Option<Task>, anyhow::Error> {
+        sqlx::query("SELECT * FROM tasks WHERE id = ?")
+            .bind(id as i64)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| task_from_row(&row))
+            .transpose()
+    }
+
+    async fn query_tasks(
+        &self,
+        owner_id: Option<u64>,
+        query: &TaskQuery,
+    ) -> Result<TaskPage, anyhow::Error> {
+        let total: i64 = filtered_tasks("SELECT COUNT(*) FROM tasks", owner_id, query)
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await?;
+
+        let column = match query.sort {
+            TaskSort::Id => "id",
+            TaskSort::Name => "name",
+            TaskSort::Completed => "completed",
+        };
+        let order = match query.order {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+        let mut select = filtered_tasks("SELECT * FROM tasks", owner_id, query);
+        select
+            .push(format!(" ORDER BY {column} {order}, id {order} LIMIT "))
+            .push_bind(i64::try_from(query.limit()).unwrap_or(i64::MAX))
+            .push(" OFFSET ")
+            .push_bind(i64::try_from(query.offset).unwrap_or(i64::MAX));
+        let tasks = select
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(task_from_row)
+            .collect::<Result<_, _>>()?;
+        Ok(TaskPage {
+            tasks,
+            total: total as u64,
+        })
+    }
+
+    async fn insert_user(
+        &self,
+        username: String,
+        password_hash: String,
+    ) -> Result<Option<User>, anyhow::Error> {
+        // The `WHERE true` keeps SQLite from parsing `ON CONFLICT` as a join
+        sqlx::query(
+            "INSERT INTO users (id, username, password_hash, role)
+             SELECT COALESCE(MAX(id), 0) + 1, ?, ?, 'user'
+             FROM users WHERE true
+             ON CONFLICT (username) DO NOTHING
+             RETURNING *",
+        )
+        .bind(username)
+        .bind(password_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| user_from_row(&row))
+        .transpose()
+    }
+
+    async fn get_user(&self, id: u64) -> Result<Option<User>, anyhow::Error> {
+        sqlx::query("SELECT * FROM users WHERE id = ?")
+            .bind(id as i64)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| user_from_row(&row))
+            .transpose()
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, anyhow::Error> {
+        sqlx::query("SELECT * FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| user_from_row(&row))
+            .transpose()
+    }
+
+    async fn set_role(&self, username: &str, role: Role) -> Result<bool, anyhow::Error> {
+        let role = match role {
+            Role::Admin => "admin",
+            Role::User => "user",
+        };
+        let result = sqlx::query("UPDATE users SET role = ? WHERE username = ?")
+            .bind(role)
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_session(
+        &self,
+        token_hash: String,
+        session: Session,
+        now: u64,
+    ) -> Result<(), anyhow::Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+            .bind(now as i64)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "INSERT OR REPLACE INTO sessions (token_hash, user_id, expires_at) VALUES (?, ?, ?)",
+        )
+        .bind(token_hash)
+        .bind(session.user_id as i64)
+        .bind(session.expires_at as i64)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<Session>, anyhow::Error> {
+        let row = sqlx::query("SELECT user_id, expires_at FROM sessions WHERE token_hash = ?")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(|row| {
+            Ok(Session {
+                user_id: row.try_get::<i64, _>("user_id")? as u64,
+                expires_at: row.try_get::<i64, _>("expires_at")? as u64,
+            })
+        })
+        .transpose()
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM sessions WHERE token_hash = ?")
+            .bind(token_hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), anyhow::Error> {
+        // Closing the last connection checkpoints the WAL into the database
+        self.pool.close().await;
+        Ok(())
+    }
+}
+
+/// `select` restricted to the tasks matching the filters of `query`.
+fn filtered_tasks<'a>(
+    select: &str,
+    owner_id: Option<u64>,
+    query: &TaskQuery,
+) -> QueryBuilder<'a, Sqlite> {
+    let mut builder = QueryBuilder::new(select);
+    builder.push(" WHERE true");
+    if let Some(owner_id) = owner_id {
+        builder.push(" AND owner_id = ").push_bind(owner_id as i64);
+    }
+    if let Some(completed) = query.completed {
+        builder.push(" AND completed = ").push_bind(completed);
+    }
+    if let Some(q) = &query.q {
+        // LIKE ignores ASCII case only, unlike the in-memory search
+        let escaped = q
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = format!("%{escaped}%");
+        builder
+            .push(" AND (name LIKE ")
+            .push_bind(pattern.clone())
+            .push(" ESCAPE '\\' OR description LIKE ")
+            .push_bind(pattern)
+            .push(" ESCAPE '\\')");
+    }
+    builder
+}
+
+fn task_from_row(row: &SqliteRow) -> Result<Task, anyhow::Error> {
+    Ok(Task {
+        id: row.try_get::<i64, _>("id")? as u64,
+        name: row.try_get("name")?,
+        description: row.try_get("description")?,
+        completed: row.try_get("completed")?,
+        owner_id: row.try_get::<i64, _>("owner_id")? as u64,
+    })
+}
+
+fn user_from_row(row: &SqliteRow) -> Result<User, anyhow::Error> {
+    let role = match row.try_get::<&str, _>("role")? {
+        "admin" => Role::Admin,
+        "user" => Role::User,
+        role => return Err(anyhow::anyhow!("Unknown role {role}")),
+    };
+    Ok(User {
+        id: row.try_get::<i64, _>("id")? as u64,
+        username: row.try_get("username")?,
+        password_hash: row.try_get("password_hash")?,
+        role,
+    })
+}
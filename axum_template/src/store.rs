@@ -0,0 +1,499 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::db::{CreateTask, Database, Role, Session, Task, TaskPage, TaskQuery, User};
+use crate::sqlite::SqliteStore;
+
+/// Where tasks, users and sessions are stored. Each operation is atomic, so
+/// handlers never need to hold a lock across calls.
+#[async_trait]
+pub trait Store: Debug + Send + Sync {
+    /// Stores a new task under the next free id and returns it. Ids of
+    /// deleted tasks are never reused.
+    async fn insert_task(&self, owner_id: u64, task: CreateTask) -> Result<Task, anyhow::Error>;
+    /// Replaces `current` with `updated` unless the stored task changed
+    /// since `current` was read. Returns `false` if it did or is gone.
+    async fn update_task(&self, current: &Task, updated: Task) -> Result<bool, anyhow::Error>;
+    /// Returns `false` if there was no task with the id.
+    async fn delete_task(&self, id: u64) -> Result<bool, anyhow::Error>;
+    async fn get_task(&self, id: u64) -> Result<Option<Task>, anyhow::Error>;
+    /// The page of tasks matching `query`, only those of `owner_id` if set.
+    async fn query_tasks(
+        &self,
+        owner_id: Option<u64>,
+        query: &TaskQuery,
+    ) -> Result<TaskPage, anyhow::Error>;
+
+    /// Adds a user with the next free id and the [`Role::User`] role.
+    /// Returns `None` if the username is taken.
+    async fn insert_user(
+        &self,
+        username: String,
+        password_hash: String,
+    ) -> Result<Option<User>, anyhow::Error>;
+    async fn get_user(&self, id: u64) -> Result<Option<User>, anyhow::Error>;
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, anyhow::Error>;
+    /// Returns `false` if there is no user named `username`.
+    async fn set_role(&self, username: &str, role: Role) -> Result<bool, anyhow::Error>;
+
+    /// Also drops the sessions that expired by `now`.
+    async fn insert_session(
+        &self,
+        token_hash: String,
+        session: Session,
+        now: u64,
+    ) -> Result<(), anyhow::Error>;
+    async fn get_session(&self, token_hash: &str) -> Result<Option<Session>, anyhow::Error>;
+    async fn delete_session(&self, token_hash: &str) -> Result<(), anyhow::Error>;
+
+    /// Writes out anything not yet on disk and releases the backing file.
+    /// Called once when the server shuts down.
+    async fn flush(&self) -> Result<(), anyhow::Error>;
+}
+
+/// Which [`Store`] to use, selected with `STORAGE_BACKEND`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StoreBackend {
+    /// A JSON file, see [`JsonStore::open`]
+    #[default]
+    Json,
+    /// Nothing is written to disk, see [`JsonStore::in_memory`]
+    Memory,
+    /// A SQLite database, see [`SqliteStore`]
+    Sqlite,
+}
+
+impl StoreBackend {
+    /// Where the data is stored unless `DATABASE_PATH` is set.
+    pub fn default_path(self) -> &'static str {
+        match self {
+            StoreBackend::Json => "database.json",
+            StoreBackend::Memory => "",
+            StoreBackend::Sqlite => "database.sqlite",
+        }
+    }
+
+    /// Opens the store at `path`, creating it if it does not exist.
+    pub async fn open(self, path: &str) -> Result<Arc<dyn Store>, anyhow::Error> {
+        Ok(match self {
+            StoreBackend::Json => Arc::new(JsonStore::open(path)?),
+            StoreBackend::Memory => Arc::new(JsonStore::in_memory()),
+            StoreBackend::Sqlite => Arc::new(SqliteStore::open(path).await?),
+        })
+    }
+}
+
+impl FromStr for StoreBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StoreBackend::Json),
+            "memory" => Ok(StoreBackend::Memory),
+            "sqlite" => Ok(StoreBackend::Sqlite),
+            _ => Err(anyhow::anyhow!(
+                "Unknown storage backend {s}, expected json, memory or sqlite"
+            )),
+        }
+    }
+}
+
+/// Keeps a [`Database`] in memory behind one lock. Unless it was created
+/// with [`JsonStore::in_memory`], each mutation is written to the backing
+/// JSON file before it returns, see [`JsonStore::save`].
+#[derive(Debug)]
+pub struct JsonStore {
+    db: Mutex<Database>,
+    /// Backing file, `None` for a store that only lives in memory
+    path: Option<PathBuf>,
+}
+
+impl JsonStore {
+    /// An empty store that is never written to disk.
+    pub fn in_memory() -> Self {
+        JsonStore {
+            db: Mutex::new(Database::new()),
+            path: None,
+        }
+    }
+
+    /// Loads the database stored at `path`, creating it if it does not exist.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let path = path.into();
+        if !path.exists() {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)
+                    .map_err(|e| anyhow::anyhow!("Failed to create {}: {e}", dir.display()))?;
+            }
+            let database = Database::new();
+            write_atomically(&path, &serde_json::to_vec(&database)?)?;
+            return Ok(JsonStore {
+                db: Mutex::new(database),
+                path: Some(path),
+            });
+        }
+        let json = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?;
+        let database: Database = serde_json::from_str(&json)
+            .map_err(|e| anyhow::anyhow!("{} is not a valid database: {e}", path.display()))?;
+        Ok(JsonStore {
+            db: Mutex::new(database),
+            path: Some(path),
+        })
+    }
+
+    /// Writes the database to the backing file with [`write_atomically`],
+    /// off the async workers.
+    async fn save(&self, database: &Database) -> Result<(), anyhow::Error> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec(database)?;
+        tokio::task::spawn_blocking(move || write_atomically(&path, &json)).await?
+    }
+
+    /// Applies `mutation` to a copy of the database and only keeps it once
+    /// it is saved, so a failed save leaves the store unchanged. The lock is
+    /// held throughout, so saves are never reordered.
+    async fn mutate<T>(
+        &self,
+        mutation: impl FnOnce(&mut Database) -> T,
+    ) -> Result<T, anyhow::Error> {
+        let mut db = self.db.lock().await;
+        let mut updated = db.clone();
+        let result = mutation(&mut updated);
+        self.save(&updated).await?;
+        *db = updated;
+        Ok(result)
+    }
+}
+
+/// Writes `json` to a temporary file next to `path`, syncs it and renames it
+/// over `path`, so a crash leaves either the old or the new contents, never a
+/// partial write.
+fn write_atomically(path: &Path, json: &[u8]) -> Result<(), anyhow::Error> {
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = File::create(&tmp_path)
+        .map_err(|e| anyhow::anyhow!("Failed to create {}: {e}", tmp_path.display()))?;
+    file.write_all(json)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+        .map_err(|e| anyhow::anyhow!("Failed to replace {}: {e}", path.display()))?;
+    // Persist the rename itself
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Store for JsonStore {
+    async fn insert_task(&self, owner_id: u64, task: CreateTask) -> Result<Task, anyhow::Error> {
+        self.mutate(|db| db.insert_task(owner_id, task)).await
+    }
+
+    async fn update_task(&self, current: &Task, updated: Task) -> Result<bool, anyhow::Error> {
+        self.mutate(|db| db.update_task(current, updated)).await
+    }
+
+    async fn delete_task(&self, id: u64) -> Result<bool, anyhow::Error> {
+        self.mutate(|db| db.delete_task(id)).await
+    }
+
+    async fn get_task(&self, id: u64) -> Result<Option<Task>, anyhow::Error> {
+        Ok(self.db.lock().await.get_task(id))
+    }
+
+    async fn query_tasks(
+        &self,
+        owner_id: Option<u64>,
+        query: &TaskQuery,
+    ) -> Result<TaskPage, anyhow::Error> {
+        Ok(self.db.lock().await.query_tasks(owner_id, query))
+    }
+
+    async fn insert_user(
+        &self,
+        username: String,
+        password_hash: String,
+    ) -> Result<Option<User>, anyhow::Error> {
+        self.mutate(|db| db.insert_user(username, password_hash))
+            .await
+    }
+
+    async fn get_user(&self, id: u64) -> Result<Option<User>, anyhow::Error> {
+        Ok(self.db.lock().await.get_user(id))
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, anyhow::Error> {
+        Ok(self.db.lock().await.get_user_by_username(username))
+    }
+
+    async fn set_role(&self, username: &str, role: Role) -> Result<bool, anyhow::Error> {
+        self.mutate(|db| db.set_role(username, role)).await
+    }
+
+    async fn insert_session(
+        &self,
+        token_hash: String,
+        session: Session,
+        now: u64,
+    ) -> Result<(), anyhow::Error> {
+        self.mutate(|db| db.insert_session(token_hash, session, now))
+            .await
+    }
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<Session>, anyhow::Error> {
+        Ok(self.db.lock().await.get_session(token_hash))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), anyhow::Error> {
+        self.mutate(|db| db.delete_session(token_hash)).await
+    }
+
+    async fn flush(&self) -> Result<(), anyhow::Error> {
+        // Mutations are already saved, this only guards against a failed save
+        self.mutate(|_| ()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SortOrder, TaskSort};
+
+    fn task(name: &str) -> CreateTask {
+        CreateTask {
+            name: name.to_string(),
+            description: String::new(),
+            completed: false,
+        }
+    }
+
+    /// A fresh directory for one test's database file.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("axum_template-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Exercises every operation, so each backend behaves the same.
+    async fn check_store(store: &dyn Store) {
+        let first = store.insert_task(1, task("task 1")).await.unwrap();
+        assert_eq!((first.id, first.owner_id), (1, 1));
+        let second = store.insert_task(2, task("task 2")).await.unwrap();
+        assert_eq!(second.id, 2);
+        assert_eq!(store.get_task(1).await.unwrap().unwrap().owner_id, 1);
+
+        let mut updated = first.clone();
+        updated.completed = true;
+        assert!(store.update_task(&first, updated.clone()).await.unwrap());
+        // `first` is stale now, so updating it again is refused
+        assert!(!store.update_task(&first, updated).await.unwrap());
+        assert!(store.get_task(1).await.unwrap().unwrap().completed);
+        let all = store.query_tasks(None, &TaskQuery::default()).await;
+        assert_eq!(all.unwrap().total, 2);
+        let owned = store
+            .query_tasks(Some(2), &TaskQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(owned.total, 1);
+        assert_eq!(owned.tasks[0].name, "task 2");
+        assert!(store.delete_task(2).await.unwrap());
+        assert!(!store.delete_task(2).await.unwrap());
+        assert!(store.get_task(2).await.unwrap().is_none());
+        // The id of the deleted task is not handed out again
+        let third = store.insert_task(1, task("task 3")).await.unwrap();
+        assert_eq!(third.id, 3);
+
+        let first = store
+            .insert_user("alice".to_string(), "hash".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((first.id, first.role), (1, Role::User));
+        let user = store
+            .insert_user("bob".to_string(), "hash".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((user.id, user.role), (2, Role::User));
+        assert!(store
+            .insert_user("alice".to_string(), "other".to_string())
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(store.get_user(2).await.unwrap().unwrap().username, "bob");
+        let found = store.get_user_by_username("alice").await.unwrap().unwrap();
+        assert_eq!(found.id, 1);
+        assert!(store.set_role("alice", Role::Admin).await.unwrap());
+        assert_eq!(store.get_user(1).await.unwrap().unwrap().role, Role::Admin);
+        assert!(!store.set_role("carol", Role::Admin).await.unwrap());
+
+        let session = |user_id, expires_at| Session {
+            user_id,
+            expires_at,
+        };
+        store
+            .insert_session("old".to_string(), session(1, 10), 0)
+            .await
+            .unwrap();
+        store
+            .insert_session("new".to_string(), session(2, 30), 20)
+            .await
+            .unwrap();
+        assert!(store.get_session("old").await.unwrap().is_none());
+        assert_eq!(store.get_session("new").await.unwrap().unwrap().user_id, 2);
+        store.delete_session("new").await.unwrap();
+        assert!(store.get_session("new").await.unwrap().is_none());
+
+        check_queries(store).await;
+    }
+
+    /// Filters, sorting and pages, on top of tasks 1 and 3 of owner 1.
+    async fn check_queries(store: &dyn Store) {
+        for (name, description, completed) in [
+            ("b: write docs", "api", false),
+            ("a: review", "Docs site", true),
+            ("c: 100% done", "", false),
+        ] {
+            let task = CreateTask {
+                name: name.to_string(),
+                description: description.to_string(),
+                completed,
+            };
+            store.insert_task(3, task).await.unwrap();
+        }
+        let names = |query: TaskQuery| async move {
+            let page = store.query_tasks(Some(3), &query).await.unwrap();
+            let names: Vec<String> = page.tasks.into_iter().map(|task| task.name).collect();
+            (names, page.total)
+        };
+
+        let (all, total) = names(TaskQuery::default()).await;
+        assert_eq!(all, ["b: write docs", "a: review", "c: 100% done"]);
+        assert_eq!(total, 3);
+        let search = TaskQuery {
+            q: Some("DOCS".to_string()),
+            ..TaskQuery::default()
+        };
+        assert_eq!(names(search).await.0, ["b: write docs", "a: review"]);
+        // `%` is matched literally, not as a wildcard
+        let search = TaskQuery {
+            q: Some("0%".to_string()),
+            ..TaskQuery::default()
+        };
+        assert_eq!(names(search).await.0, ["c: 100% done"]);
+        let open = TaskQuery {
+            completed: Some(false),
+            sort: TaskSort::Name,
+            order: SortOrder::Desc,
+            ..TaskQuery::default()
+        };
+        assert_eq!(names(open).await.0, ["c: 100% done", "b: write docs"]);
+        let page = TaskQuery {
+            sort: TaskSort::Name,
+            offset: 1,
+            limit: Some(1),
+            ..TaskQuery::default()
+        };
+        assert_eq!(names(page).await, (vec!["b: write docs".to_string()], 3));
+    }
+
+    #[tokio::test]
+    async fn every_backend_behaves_the_same() {
+        let dir = test_dir("backends");
+        for backend in [
+            StoreBackend::Json,
+            StoreBackend::Memory,
+            StoreBackend::Sqlite,
+        ] {
+            let path = dir.join(backend.default_path());
+            let store = backend.open(path.to_str().unwrap()).await.unwrap();
+            check_store(store.as_ref()).await;
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn mutations_survive_a_restart() {
+        let dir = test_dir("restart");
+        for backend in [StoreBackend::Json, StoreBackend::Sqlite] {
+            let path = dir.join(backend.default_path());
+            let path = path.to_str().unwrap();
+
+            let store = backend.open(path).await.unwrap();
+            store.insert_task(1, task("task 1")).await.unwrap();
+            store.insert_task(1, task("task 2")).await.unwrap();
+            store.delete_task(2).await.unwrap();
+            drop(store);
+
+            let reopened = backend.open(path).await.unwrap();
+            assert!(reopened.get_task(2).await.unwrap().is_none());
+            assert_eq!(reopened.get_task(1).await.unwrap().unwrap().name, "task 1");
+            let next = reopened.insert_task(1, task("task 3")).await.unwrap();
+            assert_eq!(next.id, 3);
+        }
+        // Only the JSON file is left behind, not its temporary file
+        assert!(!dir.join("database.json.tmp").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_saves_leave_the_store_unchanged() {
+        let dir = test_dir("failed-save");
+        let data_dir = dir.join("data");
+        let store = JsonStore::open(data_dir.join("database.json")).unwrap();
+        store.insert_task(1, task("task 1")).await.unwrap();
+
+        // Nothing can be written once the directory is gone
+        std::fs::remove_dir_all(&data_dir).unwrap();
+        assert!(store.insert_task(1, task("task 2")).await.is_err());
+        assert!(store.delete_task(1).await.is_err());
+        assert!(store.get_task(2).await.unwrap().is_none());
+        assert_eq!(store.get_task(1).await.unwrap().unwrap().name, "task 1");
+
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let next = store.insert_task(1, task("task 2")).await.unwrap();
+        assert_eq!(next.id, 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn open_creates_missing_files() {
+        let dir = test_dir("create");
+        let path = dir.join("data").join("database.json");
+        JsonStore::open(&path).unwrap();
+        assert!(path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn open_rejects_corrupt_files() {
+        let dir = test_dir("corrupt");
+        let path = dir.join("database.json");
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(JsonStore::open(&path).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn backends_are_parsed_by_name() {
+        assert_eq!(
+            "sqlite".parse::<StoreBackend>().unwrap(),
+            StoreBackend::Sqlite
+        );
+        assert!("postgres".parse::<StoreBackend>().is_err());
+    }
+}
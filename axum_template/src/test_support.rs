@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderName, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use crate::config::Config;
+use crate::store::JsonStore;
+use crate::AppState;
+
+pub fn app_state() -> AppState {
+    AppState {
+        db: Arc::new(JsonStore::in_memory()),
+    }
+}
+
+/// Sends a request through the full router.
+pub async fn send(
+    app_state: &AppState,
+    method: &str,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> Response<Body> {
+    send_with_headers(app_state, method, uri, token, &[], body).await
+}
+
+/// Like [`send`], with extra request headers.
+pub async fn send_with_headers(
+    app_state: &AppState,
+    method: &str,
+    uri: &str,
+    token: Option<&str>,
+    headers: &[(HeaderName, &str)],
+    body: Option<Value>,
+) -> Response<Body> {
+    let mut request = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        request = request.header(name, *value);
+    }
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+    }
+    let body = match body {
+        Some(body) => {
+            request = request.header(CONTENT_TYPE, "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    crate::router(app_state.clone(), &Config::default())
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap()
+}
+
+pub async fn json_body(response: Response<Body>) -> Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+/// Registers `username` and returns a session token for it.
+pub async fn log_in(app_state: &AppState, username: &str) -> String {
+    let credentials = json!({ "username": username, "password": "hunter2" });
+    send(
+        app_state,
+        "POST",
+        "/register",
+        None,
+        Some(credentials.clone()),
+    )
+    .await;
+    let response = send(app_state, "POST", "/login", None, Some(credentials)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    json_body(response).await["token"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
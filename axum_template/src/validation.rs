@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::db::{CreateTask, Credentials, PatchTask, TaskQuery, UpdateTask, MAX_PAGE_SIZE};
+use crate::error::AppError;
+
+const MAX_USERNAME_CHARS: usize = 64;
+/// Argon2 hashes the whole password, so unbounded ones are a cheap DoS
+const MAX_PASSWORD_BYTES: usize = 1024;
+const MAX_TASK_NAME_CHARS: usize = 200;
+const MAX_TASK_DESCRIPTION_CHARS: usize = 10_000;
+
+/// A request field that failed validation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Request bodies that are checked before handlers act on them.
+pub trait Validate {
+    /// Every invalid field, empty if the request is valid.
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+/// Rejects `request` with a 422 listing its invalid fields.
+pub fn validate(request: &impl Validate) -> Result<(), AppError> {
+    let details = request.validate();
+    if details.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::Validation(details))
+}
+
+impl Validate for Credentials {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.username.is_empty() {
+            errors.push(field_error("username", "must not be empty"));
+        } else if self.username.chars().count() > MAX_USERNAME_CHARS {
+            errors.push(field_error(
+                "username",
+                format!("must be at most {MAX_USERNAME_CHARS} characters"),
+            ));
+        } else if !self
+            .username
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            errors.push(field_error(
+                "username",
+                "may only contain letters, digits, '_', '-' and '.'",
+            ));
+        }
+        if self.password.is_empty() {
+            errors.push(field_error("password", "must not be empty"));
+        } else if self.password.len() > MAX_PASSWORD_BYTES {
+            errors.push(field_error(
+                "password",
+                format!("must be at most {MAX_PASSWORD_BYTES} bytes"),
+            ));
+        }
+        errors
+    }
+}
+
+impl Validate for CreateTask {
+    fn validate(&self) -> Vec<FieldError> {
+        task_errors(Some(&self.name), Some(&self.description))
+    }
+}
+
+impl Validate for UpdateTask {
+    fn validate(&self) -> Vec<FieldError> {
+        task_errors(Some(&self.name), Some(&self.description))
+    }
+}
+
+impl Validate for PatchTask {
+    fn validate(&self) -> Vec<FieldError> {
+        task_errors(self.name.as_deref(), self.description.as_deref())
+    }
+}
+
+impl Validate for TaskQuery {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if !(1..=MAX_PAGE_SIZE).contains(&self.limit()) {
+            errors.push(field_error(
+                "limit",
+                format!("must be between 1 and {MAX_PAGE_SIZE}"),
+            ));
+        }
+        errors
+    }
+}
+
+/// Checks the task fields that are present.
+fn task_errors(name: Option<&str>, description: Option<&str>) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if let Some(name) = name {
+        if name.trim().is_empty() {
+            errors.push(field_error("name", "must not be empty"));
+        } else if name.chars().count() > MAX_TASK_NAME_CHARS {
+            errors.push(field_error(
+                "name",
+                format!("must be at most {MAX_TASK_NAME_CHARS} characters"),
+            ));
+        }
+    }
+    if description
+        .is_some_and(|description| description.chars().count() > MAX_TASK_DESCRIPTION_CHARS)
+    {
+        errors.push(field_error(
+            "description",
+            format!("must be at most {MAX_TASK_DESCRIPTION_CHARS} characters"),
+        ));
+    }
+    errors
+}
+
+fn field_error(field: &str, message: impl Into<String>) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    fn fields(errors: Vec<FieldError>) -> Vec<String> {
+        errors.into_iter().map(|error| error.field).collect()
+    }
+
+    #[test]
+    fn credentials_need_a_plain_username_and_a_password() {
+        assert!(credentials("alice.b-c_1", "hunter2").validate().is_empty());
+        assert_eq!(
+            fields(credentials("", "").validate()),
+            ["username", "password"]
+        );
+        assert_eq!(fields(credentials("al ice", "x").validate()), ["username"]);
+        let long = "a".repeat(MAX_USERNAME_CHARS + 1);
+        assert_eq!(fields(credentials(&long, "x").validate()), ["username"]);
+        let long = "a".repeat(MAX_PASSWORD_BYTES + 1);
+        assert_eq!(fields(credentials("alice", &long).validate()), ["password"]);
+    }
+
+    #[test]
+    fn tasks_need_a_name() {
+        let task = |name: &str, description: &str| CreateTask {
+            name: name.to_string(),
+            description: description.to_string(),
+            completed: false,
+        };
+        assert!(task("write docs", "").validate().is_empty());
+        assert_eq!(fields(task(" ", "").validate()), ["name"]);
+        let long = "a".repeat(MAX_TASK_DESCRIPTION_CHARS + 1);
+        assert_eq!(fields(task("x", &long).validate()), ["description"]);
+
+        assert!(PatchTask::default().validate().is_empty());
+        let patch = PatchTask {
+            name: Some(String::new()),
+            ..PatchTask::default()
+        };
+        assert_eq!(fields(patch.validate()), ["name"]);
+    }
+}
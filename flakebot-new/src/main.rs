@@ -1,3 +1,8 @@
-fn main() {
-    println!("Hello, world!");
+use clap::Parser;
+use flakebot_original::config::CliArgs;
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    flakebot_original::init_logging_and_env()?;
+    flakebot_original::run(CliArgs::parse()).await
 }
@@ -1,131 +1,672 @@
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use fs_extra::dir;
 use fs_extra::dir::CopyOptions;
-use serde::Deserialize;
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 use tracing::{error, info};
 
+use crate::attestation::{self, BinaryDigest, BuildRecord};
+use crate::builder::{self, Builder};
+use crate::cargo::{self, BinaryTarget};
 use crate::config;
-use crate::crates_io::CratesIo;
-use crate::flake::Flake;
-use crate::github::Github;
-use crate::groq::Groq;
-
-// macro_rules! wait_for_enter {
-//     () => {{
-//         use tokio::io::{self, AsyncBufReadExt, BufReader};
-//         let mut reader = BufReader::new(io::stdin());
-//         let mut pause = String::new();
-//         println!("Press ENTER to continue...");
-//         reader.read_line(&mut pause).await?;
-//     }};
-// }
-
-#[derive(Deserialize)]
-struct Crate {
-    _crate_id: String,
-    _repository: Option<String>,
+use crate::crates_io::{CrateRegistry, CratesIo};
+use crate::flake::{self, Flake, InputOverride};
+use crate::github::{CodeHost, Github};
+use crate::groq::{Groq, Llm};
+use crate::manifest::{self, FlakeReference, Invocation, ToolManifest};
+use crate::registry::{self, Registry};
+use crate::scaffold;
+use crate::settings::{GitSettings, Settings};
+use crate::smoke::{self, SmokeReport, ToolKind};
+
+/// Where the OpenAPI document of an HTTP tool is committed in its repository.
+pub const OPENAPI_FILE_NAME: &str = "openapi.json";
+
+/// Where the progress of a run is saved in the work directory.
+pub const STATE_FILE_NAME: &str = "flakebot-state.json";
+
+/// The steps of a run, in the order they are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Find a crate for the instructions, fork it and open the flake.nix PR
+    Flake,
+    /// Open the PR installing the flakebox files
+    Flakebox,
+    /// Rewrite the program until it satisfies the instructions
+    Feature,
+    /// Build the binaries and sign the build attestation
+    Build,
+    /// Smoke test the built tool and derive its API contract
+    Verify,
+    /// Push the feature, open its PR and register the tool
+    Pr,
 }
 
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Stage::Flake,
+        Stage::Flakebox,
+        Stage::Feature,
+        Stage::Build,
+        Stage::Verify,
+        Stage::Pr,
+    ];
+
+    /// The `stages` to run, every stage if none are given, without the
+    /// `skip`ped ones, in pipeline order.
+    pub fn select(stages: &[Stage], skip: &[Stage]) -> Vec<Stage> {
+        Stage::ALL
+            .into_iter()
+            .filter(|stage| stages.is_empty() || stages.contains(stage))
+            .filter(|stage| !skip.contains(stage))
+            .collect()
+    }
+}
+
+/// Everything a stage hands to the following ones, saved after every stage so
+/// a run can be resumed from another process.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunState {
+    pub instructions: String,
+    pub repo_url: Option<String>,
+    pub repo_name: Option<String>,
+    /// Local checkout the run works in instead of a fork cloned into the
+    /// work directory
+    #[serde(default)]
+    pub repo_path: Option<PathBuf>,
+    pub crate_description: Option<String>,
+    pub binaries: Vec<BinaryTarget>,
+    pub build_record: Option<BuildRecord>,
+    pub tool_kind: Option<ToolKind>,
+    pub smoke_report: Option<SmokeReport>,
+    pub openapi: Option<serde_json::Value>,
+    pub completed: Vec<Stage>,
+}
+
+impl RunState {
+    /// Loads the state saved in `work_dir`, if a run was started there.
+    pub fn load(work_dir: &Path) -> Result<Option<RunState>, anyhow::Error> {
+        let state_path = work_dir.join(STATE_FILE_NAME);
+        if !state_path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&state_path)?;
+        let state = serde_json::from_str(&contents).map_err(|e| {
+            anyhow::anyhow!("{} is not a valid run state: {}", state_path.display(), e)
+        })?;
+        Ok(Some(state))
+    }
+
+    pub fn save(&self, work_dir: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(
+            work_dir.join(STATE_FILE_NAME),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// The pipeline turning instructions into a built, tested and registered
+/// tool. Each [`Stage`] can be run on its own; progress is kept in the work
+/// directory so a run can continue in another process.
 pub struct App {
-    instructions: String,
     work_dir: PathBuf,
+    state: RunState,
+    llm: Box<dyn Llm>,
+    code_host: Box<dyn CodeHost>,
+    crate_registry: Box<dyn CrateRegistry>,
+    override_inputs: Vec<InputOverride>,
+    flake_registry: Option<PathBuf>,
+    reference_flake: Option<PathBuf>,
+    attestation_key: PathBuf,
+    requested_binaries: Vec<String>,
+    builder: Box<dyn Builder>,
+    smoke_startup_timeout: Duration,
+    daemon_uptime: Duration,
+    tool_kind: Option<ToolKind>,
+    registry_dir: PathBuf,
+    git: GitSettings,
+    stages: Vec<Stage>,
+    target: Target,
+}
+
+/// How the flake stage picks the repository when no local checkout is given.
+/// Without any of these the LLM suggests a crate for the instructions.
+#[derive(Debug, Clone, Default)]
+struct Target {
+    /// Repository to fork instead of looking a crate up
     repo_url: Option<String>,
-    repo_name: Option<String>,
-    groq: Groq,
-    github: Github,
-    crates_io: CratesIo,
-    flake: Option<Flake>,
+    /// Crate looked up on crates.io instead of the LLM's suggestion
+    crate_name: Option<String>,
+    /// New crate created instead of looking one up
+    scaffold: Option<String>,
+    /// Template for new crates, also used when no crate is found
+    template: Option<PathBuf>,
 }
 
-impl App {
-    pub async fn new(cli_args: &config::CliArgs) -> Result<App, anyhow::Error> {
-        let groq = Groq::new(&cli_args.groq_api_key);
-        let github = Github::new(cli_args.github_token.clone());
-        let crates_io = CratesIo::new(cli_args.cargo_cookie.clone());
+/// Assembles an [`App`] from its services. The LLM, code host, crate registry
+/// and builder are required, everything else has the CLI's defaults.
+pub struct AppBuilder {
+    work_dir: PathBuf,
+    instructions: Option<String>,
+    llm: Option<Box<dyn Llm>>,
+    code_host: Option<Box<dyn CodeHost>>,
+    crate_registry: Option<Box<dyn CrateRegistry>>,
+    builder: Option<Box<dyn Builder>>,
+    override_inputs: Vec<InputOverride>,
+    flake_registry: Option<PathBuf>,
+    reference_flake: Option<PathBuf>,
+    attestation_key: Option<PathBuf>,
+    binaries: Vec<String>,
+    smoke_startup_timeout: Duration,
+    daemon_uptime: Duration,
+    tool_kind: Option<ToolKind>,
+    registry_dir: Option<PathBuf>,
+    git: GitSettings,
+    stages: Vec<Stage>,
+    repo_path: Option<PathBuf>,
+    target: Target,
+}
+
+impl AppBuilder {
+    pub fn new(work_dir: impl Into<PathBuf>) -> Self {
+        AppBuilder {
+            work_dir: work_dir.into(),
+            instructions: None,
+            llm: None,
+            code_host: None,
+            crate_registry: None,
+            builder: None,
+            override_inputs: Vec::new(),
+            flake_registry: None,
+            reference_flake: None,
+            attestation_key: None,
+            binaries: Vec::new(),
+            smoke_startup_timeout: Duration::from_secs(30),
+            daemon_uptime: Duration::from_secs(10),
+            tool_kind: None,
+            registry_dir: None,
+            git: GitSettings::default(),
+            stages: Stage::ALL.to_vec(),
+            repo_path: None,
+            target: Target::default(),
+        }
+    }
+
+    /// Starts a new run unless the run saved in the work directory has the
+    /// same instructions. Without instructions the saved run is continued.
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    pub fn llm(mut self, llm: Box<dyn Llm>) -> Self {
+        self.llm = Some(llm);
+        self
+    }
+
+    pub fn code_host(mut self, code_host: Box<dyn CodeHost>) -> Self {
+        self.code_host = Some(code_host);
+        self
+    }
+
+    pub fn crate_registry(mut self, crate_registry: Box<dyn CrateRegistry>) -> Self {
+        self.crate_registry = Some(crate_registry);
+        self
+    }
+
+    pub fn builder(mut self, builder: Box<dyn Builder>) -> Self {
+        self.builder = Some(builder);
+        self
+    }
+
+    /// Flake inputs overridden when locking.
+    pub fn override_inputs(mut self, override_inputs: Vec<InputOverride>) -> Self {
+        self.override_inputs = override_inputs;
+        self
+    }
+
+    /// Flake registry used to resolve indirect flake inputs when locking.
+    pub fn flake_registry(mut self, flake_registry: impl Into<PathBuf>) -> Self {
+        self.flake_registry = Some(flake_registry.into());
+        self
+    }
+
+    /// flake.nix template for repositories without one, instead of
+    /// [`flake::REFERENCE_FLAKE`].
+    pub fn reference_flake(mut self, reference_flake: impl Into<PathBuf>) -> Self {
+        self.reference_flake = Some(reference_flake.into());
+        self
+    }
+
+    /// Signing key for build attestations, `attestation.key` in the work
+    /// directory by default.
+    pub fn attestation_key(mut self, attestation_key: impl Into<PathBuf>) -> Self {
+        self.attestation_key = Some(attestation_key.into());
+        self
+    }
+
+    /// Binary targets to build by name, all of them by default.
+    pub fn binaries(mut self, binaries: Vec<String>) -> Self {
+        self.binaries = binaries;
+        self
+    }
+
+    pub fn smoke_startup_timeout(mut self, smoke_startup_timeout: Duration) -> Self {
+        self.smoke_startup_timeout = smoke_startup_timeout;
+        self
+    }
+
+    /// How long a daemon must keep running to pass its smoke test.
+    pub fn daemon_uptime(mut self, daemon_uptime: Duration) -> Self {
+        self.daemon_uptime = daemon_uptime;
+        self
+    }
+
+    /// Skips classifying the tool with the LLM.
+    pub fn tool_kind(mut self, tool_kind: ToolKind) -> Self {
+        self.tool_kind = Some(tool_kind);
+        self
+    }
+
+    /// Registry built tools are added to, see [`registry::default_dir`].
+    pub fn registry_dir(mut self, registry_dir: impl Into<PathBuf>) -> Self {
+        self.registry_dir = Some(registry_dir.into());
+        self
+    }
+
+    /// Branch names, commit author and `.gitignore` used in the repository.
+    pub fn git(mut self, git: GitSettings) -> Self {
+        self.git = git;
+        self
+    }
+
+    /// Stages run by [`App::run`] and [`App::resume`], all of them by default.
+    pub fn stages(mut self, stages: Vec<Stage>) -> Self {
+        self.stages = stages;
+        self
+    }
+
+    /// Works in an existing checkout instead of finding a crate and forking
+    /// it. Its `origin` remote is where branches are pushed.
+    pub fn repo_path(mut self, repo_path: impl Into<PathBuf>) -> Self {
+        self.repo_path = Some(repo_path.into());
+        self
+    }
+
+    /// Forks this repository instead of looking a crate up.
+    pub fn repo_url(mut self, repo_url: &str) -> Self {
+        let repo_url = repo_url.trim_end_matches('/');
+        let repo_url = repo_url.strip_suffix(".git").unwrap_or(repo_url);
+        self.target.repo_url = Some(repo_url.to_string());
+        self
+    }
+
+    /// Looks this crate up instead of asking the LLM for one.
+    pub fn crate_name(mut self, crate_name: impl Into<String>) -> Self {
+        self.target.crate_name = Some(crate_name.into());
+        self
+    }
+
+    /// Creates a new crate with this name instead of looking one up.
+    pub fn scaffold(mut self, crate_name: impl Into<String>) -> Self {
+        self.target.scaffold = Some(crate_name.into());
+        self
+    }
+
+    /// Crate copied into scaffolded crates. With a template, a crate that
+    /// cannot be found on crates.io is scaffolded instead.
+    pub fn template(mut self, template: impl Into<PathBuf>) -> Self {
+        self.target.template = Some(template.into());
+        self
+    }
+
+    pub fn build(self) -> Result<App, anyhow::Error> {
         // Ensure the work directory exists
-        if !cli_args.work_dir.exists() {
-            std::fs::create_dir_all(&cli_args.work_dir).map_err(|e| {
+        if !self.work_dir.exists() {
+            std::fs::create_dir_all(&self.work_dir).map_err(|e| {
                 anyhow::anyhow!(
                     "Failed to create work directory at {}: {}",
-                    cli_args.work_dir.display(),
+                    self.work_dir.display(),
                     e
                 )
             })?;
         }
 
+        let mut state = match (RunState::load(&self.work_dir)?, self.instructions) {
+            (Some(state), None) => state,
+            (Some(state), Some(instructions)) if state.instructions == instructions => state,
+            (_, Some(instructions)) => RunState {
+                instructions,
+                ..RunState::default()
+            },
+            // Nixifying a local checkout needs no instructions
+            (None, None) if self.repo_path.is_some() => RunState::default(),
+            (None, None) => {
+                return Err(anyhow::anyhow!(
+                    "No run found in {}, pass --instructions to start one",
+                    self.work_dir.display()
+                ))
+            }
+        };
+
+        if let Some(repo_path) = self.repo_path {
+            use_local_checkout(&mut state, &repo_path)?;
+        }
+
         Ok(App {
-            instructions: cli_args.instructions.clone(),
-            work_dir: cli_args.work_dir.clone(),
-            repo_url: None,
-            repo_name: None,
-            groq,
-            github,
-            crates_io,
-            flake: None,
+            attestation_key: self
+                .attestation_key
+                .unwrap_or_else(|| self.work_dir.join("attestation.key")),
+            registry_dir: match self.registry_dir {
+                Some(registry_dir) => registry_dir,
+                None => registry::default_dir()?,
+            },
+            work_dir: self.work_dir,
+            state,
+            llm: self
+                .llm
+                .ok_or_else(|| anyhow::anyhow!("No LLM configured"))?,
+            code_host: self
+                .code_host
+                .ok_or_else(|| anyhow::anyhow!("No code host configured"))?,
+            crate_registry: self
+                .crate_registry
+                .ok_or_else(|| anyhow::anyhow!("No crate registry configured"))?,
+            builder: self
+                .builder
+                .ok_or_else(|| anyhow::anyhow!("No builder configured"))?,
+            override_inputs: self.override_inputs,
+            flake_registry: self.flake_registry,
+            reference_flake: self.reference_flake,
+            requested_binaries: self.binaries,
+            smoke_startup_timeout: self.smoke_startup_timeout,
+            daemon_uptime: self.daemon_uptime,
+            tool_kind: self.tool_kind,
+            git: self.git,
+            stages: self.stages,
+            target: self.target,
         })
     }
+}
 
-    pub async fn run(&mut self) -> Result<(), anyhow::Error> {
-        let tool = self.identify_and_validate_tool().await?;
-        let repo_url = self.find_crate_and_set_repo(tool).await?;
-        self.prepare_repository(repo_url).await?;
-        self.process_repository_files().await?;
-        let repo_dir = self.work_dir.join(
-            self.repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-        );
-        self.github.create_branch(&repo_dir, "nix-flake").await?;
-
-        // First PR: flake.nix
-        self.flake
-            .as_mut()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get flake"))?
-            .ensure_flake_nix(&PathBuf::from(
-                "/Users/kody/Documents/github/deterministic_program_crafter/reference_flake.nix",
+impl App {
+    pub fn builder(work_dir: impl Into<PathBuf>) -> AppBuilder {
+        AppBuilder::new(work_dir)
+    }
+
+    /// Builds the app the CLI runs: Groq, GitHub, crates.io and the builder
+    /// selected by the arguments merged with the configuration files.
+    pub async fn new(cli_args: &config::CraftArgs) -> Result<App, anyhow::Error> {
+        let settings = cli_args.settings()?;
+        let targets = [
+            settings.repo.is_some(),
+            settings.repo_path.is_some(),
+            settings.crate_name.is_some(),
+            settings.scaffold.is_some(),
+        ];
+        if targets.into_iter().filter(|target| *target).count() > 1 {
+            return Err(anyhow::anyhow!(
+                "Pass only one of --repo, --repo-path, --crate and --scaffold"
+            ));
+        }
+        let groq_api_key =
+            Settings::secret(&settings.groq_api_key, "groq_api_key", "GROQ_API_KEY")?;
+        let github_token =
+            Settings::secret(&settings.github_token, "github_token", "GITHUB_TOKEN")?;
+        let cargo_cookie =
+            Settings::secret(&settings.cargo_cookie, "cargo_cookie", "CARGO_COOKIE")?;
+        let mut app_builder = App::builder(&settings.work_dir)
+            .llm(Box::new(
+                Groq::new(groq_api_key)
+                    .model(&settings.llm.model)
+                    .base_url(&settings.llm.base_url),
             ))
+            .code_host(Box::new(Github::new(github_token.to_string())))
+            .crate_registry(Box::new(CratesIo::new(cargo_cookie.to_string())))
+            .builder(builder::from_kind(settings.builder, &settings.rust_toolchain).await)
+            .override_inputs(settings.override_inputs.clone())
+            .binaries(settings.binaries.clone())
+            .smoke_startup_timeout(Duration::from_secs(settings.smoke_startup_timeout))
+            .daemon_uptime(Duration::from_secs(settings.daemon_uptime))
+            .git(settings.git.clone())
+            .stages(Stage::select(&settings.stages, &settings.skip));
+        if let Some(instructions) = &cli_args.instructions {
+            app_builder = app_builder.instructions(instructions);
+        }
+        if let Some(flake_registry) = &settings.flake_registry {
+            app_builder = app_builder.flake_registry(flake_registry);
+        }
+        if let Some(reference_flake) = &settings.reference_flake {
+            app_builder = app_builder.reference_flake(reference_flake);
+        }
+        if let Some(attestation_key) = &settings.attestation_key {
+            app_builder = app_builder.attestation_key(attestation_key);
+        }
+        if let Some(tool_kind) = settings.tool_kind {
+            app_builder = app_builder.tool_kind(tool_kind);
+        }
+        if let Some(registry_dir) = &settings.registry_dir {
+            app_builder = app_builder.registry_dir(registry_dir);
+        }
+        if let Some(repo_path) = &settings.repo_path {
+            app_builder = app_builder.repo_path(repo_path);
+        }
+        if let Some(repo) = &settings.repo {
+            app_builder = match git_url(repo) {
+                Some(repo_url) => app_builder.repo_url(&repo_url),
+                None => app_builder.repo_path(repo),
+            };
+        }
+        if let Some(crate_name) = &settings.crate_name {
+            app_builder = app_builder.crate_name(crate_name);
+        }
+        if let Some(scaffold) = &settings.scaffold {
+            app_builder = app_builder.scaffold(scaffold);
+        }
+        if let Some(template) = &settings.template {
+            app_builder = app_builder.template(template);
+        }
+        app_builder.build()
+    }
+
+    pub fn work_dir(&self) -> &Path {
+        &self.work_dir
+    }
+
+    pub fn state(&self) -> &RunState {
+        &self.state
+    }
+
+    /// Runs the selected stages from the start.
+    pub async fn run(&mut self) -> Result<(), anyhow::Error> {
+        self.state.completed.clear();
+        for stage in self.stages.clone() {
+            self.run_stage(stage).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the selected stages the saved run has not completed yet.
+    pub async fn resume(&mut self) -> Result<(), anyhow::Error> {
+        let remaining: Vec<Stage> = self
+            .stages
+            .iter()
+            .copied()
+            .filter(|stage| !self.state.completed.contains(stage))
+            .collect();
+        if remaining.is_empty() {
+            info!("Every stage of the run is already completed");
+        }
+        for stage in remaining {
+            self.run_stage(stage).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs a single stage and saves the run state once it completes.
+    pub async fn run_stage(&mut self, stage: Stage) -> Result<(), anyhow::Error> {
+        info!("Running the {:?} stage", stage);
+        match stage {
+            Stage::Flake => self.flake_stage().await?,
+            Stage::Flakebox => self.flakebox_stage().await?,
+            Stage::Feature => self.feature_stage().await?,
+            Stage::Build => self.build_stage().await?,
+            Stage::Verify => self.verify_stage().await?,
+            Stage::Pr => self.pr_stage().await?,
+        }
+        self.state.completed.retain(|completed| *completed != stage);
+        self.state.completed.push(stage);
+        self.state.save(&self.work_dir)?;
+        Ok(())
+    }
+
+    /// First PR: flake.nix
+    async fn flake_stage(&mut self) -> Result<(), anyhow::Error> {
+        // A local checkout is nixified as is, without looking for a crate
+        if self.state.repo_path.is_none() {
+            match self.find_repository().await? {
+                Ok(repo_url) => {
+                    self.set_repo_url(&repo_url)?;
+                    // Save the repository early so later stages find it if this one fails
+                    self.state.save(&self.work_dir)?;
+                    self.prepare_repository(repo_url).await?;
+                }
+                Err(crate_name) => self.scaffold_repository(&crate_name).await?,
+            }
+        }
+        let repo_dir = self.repo_dir()?;
+        self.code_host
+            .create_branch(&repo_dir, &self.git.flake_branch)
+            .await?;
+
+        let flake = self.flake()?;
+        flake
+            .ensure_flake_nix(self.reference_flake.as_deref())
             .await?;
         self.update_and_write_flake().await?;
-        self.flake
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get flake"))?
-            .check_flake_nix()
+        let locked_inputs = flake
+            .lock(&self.override_inputs, self.flake_registry.as_deref())
             .await?;
+        flake.check_flake_nix().await?;
         self.commit_changes(true).await?;
         self.push_changes(false).await?;
-        self.github
-            .open_pull_request(
-                &self.work_dir.join(
-                    self.repo_name
-                        .as_ref()
-                        .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-                ),
-                &self.groq,
-            )
+        self.open_pull_request(Some(&flake::pinned_inputs_summary(&locked_inputs)))
             .await?;
+        Ok(())
+    }
 
-        // Second PR: flakebox
-        self.github.create_branch(&repo_dir, "flakebox").await?;
+    /// Second PR: flakebox
+    async fn flakebox_stage(&mut self) -> Result<(), anyhow::Error> {
+        let repo_dir = self.repo_dir()?;
+        self.code_host
+            .create_branch(&repo_dir, &self.git.flakebox_branch)
+            .await?;
         self.install_flakebox_files(&repo_dir).await?;
         self.push_changes(false).await?;
-        self.github.open_pull_request(&repo_dir, &self.groq).await?;
-
-        // Third PR: main.rs updates
-        self.github.create_branch(&repo_dir, "new-feature").await?;
-        self.validate_and_check_program(repo_dir.clone()).await?;
-        let binary_path = self.build_and_output_binary().await?;
-        self.run_binary(&binary_path).await?;
-        self.push_changes(true).await?;
-        self.github.open_pull_request(&repo_dir, &self.groq).await?;
+        self.open_pull_request(None).await?;
+        Ok(())
+    }
+
+    /// Third PR, first half: main.rs updates
+    async fn feature_stage(&mut self) -> Result<(), anyhow::Error> {
+        if self.state.instructions.is_empty() {
+            return Err(anyhow::anyhow!(
+                "The feature stage needs --instructions describing the feature"
+            ));
+        }
+        let repo_dir = self.repo_dir()?;
+        self.code_host
+            .create_branch(&repo_dir, &self.git.feature_branch)
+            .await?;
+        self.validate_and_check_program(repo_dir).await?;
+        // Commit before building so the attestation refers to the pushed revision
+        self.commit_changes(true).await?;
+        Ok(())
+    }
+
+    async fn build_stage(&mut self) -> Result<(), anyhow::Error> {
+        // Commit what the builder adds, e.g. a pinned toolchain, so the attested
+        // revision contains everything the binaries were built from
+        self.builder.prepare(&self.repo_dir()?)?;
+        self.commit_changes(false).await?;
+        let build_record = self.build_and_output_binary().await?;
+        self.write_attestation(&build_record)?;
+        self.state.build_record = Some(build_record);
+        Ok(())
+    }
+
+    async fn verify_stage(&mut self) -> Result<(), anyhow::Error> {
+        let tool_kind = self.classify_tool().await?;
+        let binary_path = self
+            .state
+            .binaries
+            .first()
+            .map(|binary| self.work_dir.join("bin").join(&binary.name));
+        let smoke_report = self.smoke_test(tool_kind, binary_path.as_deref()).await?;
+        self.state.openapi = if tool_kind == ToolKind::HttpServer {
+            let spec = self.write_openapi_spec(&smoke_report).await?;
+            self.commit_changes(false).await?;
+            Some(spec)
+        } else {
+            None
+        };
+        self.state.tool_kind = Some(tool_kind);
+        self.state.smoke_report = Some(smoke_report);
+        Ok(())
+    }
+
+    /// Third PR, second half: push the feature with its build and test results
+    async fn pr_stage(&mut self) -> Result<(), anyhow::Error> {
+        let mut build_record = self
+            .state
+            .build_record
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No build recorded, run the build stage first"))?;
+        let (Some(tool_kind), Some(smoke_report)) =
+            (self.state.tool_kind, self.state.smoke_report.clone())
+        else {
+            return Err(anyhow::anyhow!(
+                "No smoke test report, run the verify stage first"
+            ));
+        };
+
+        // The pushed revision has to be the attested one
+        self.commit_changes(true).await?;
+        self.attest_head(&mut build_record)?;
+        self.state.build_record = Some(build_record.clone());
+        self.push().await?;
+
+        let mut pr_body = format!(
+            "{}\n{}",
+            attestation::attestation_summary(&build_record),
+            smoke_report.summary()
+        );
+        if tool_kind == ToolKind::HttpServer {
+            pr_body.push_str(&format!(
+                "\n### API contract\n\nOpenAPI document committed at `{}`.\n",
+                OPENAPI_FILE_NAME
+            ));
+        }
+        self.open_pull_request(Some(&pr_body)).await?;
+        let manifest = self
+            .write_manifest(
+                tool_kind,
+                &build_record,
+                &smoke_report,
+                self.state.openapi.clone(),
+            )
+            .await?;
+        self.register_tool(&manifest, &self.attestation_path())?;
         Ok(())
     }
 
     async fn identify_tool(&self) -> Result<Option<String>, anyhow::Error> {
-        let crates = self.groq.get_crates_list(&self.instructions).await?;
+        let crates = self.llm.get_crates_list(&self.state.instructions).await?;
         info!("Tools identified: {}", crates.join(", "));
         let first_tool = crates.first().cloned();
         info!(
@@ -143,55 +684,99 @@ impl App {
         Ok(tool)
     }
 
-    async fn find_crate_and_set_repo(&mut self, tool: String) -> Result<String, anyhow::Error> {
-        let repo_url = self
-            .crates_io
-            .search(&tool)
-            .await
-            .ok_or_else(|| anyhow::anyhow!("Failed to find crate on crates.io"))?;
-        self.repo_url = Some(repo_url.clone());
-        let repo_name = repo_url.split('/').last().ok_or_else(|| {
+    /// The URL of the repository to fork, or the name of the crate to
+    /// scaffold when there is none.
+    async fn find_repository(&self) -> Result<Result<String, String>, anyhow::Error> {
+        if let Some(crate_name) = &self.target.scaffold {
+            return Ok(Err(crate_name.clone()));
+        }
+        if let Some(repo_url) = &self.target.repo_url {
+            return Ok(Ok(repo_url.clone()));
+        }
+        let crate_name = match &self.target.crate_name {
+            Some(crate_name) => crate_name.clone(),
+            None => self.identify_and_validate_tool().await?,
+        };
+        match self.crate_registry.search(&crate_name).await {
+            Some(repo_url) => Ok(Ok(repo_url)),
+            None if self.target.template.is_some() => {
+                info!("No repository found for {}, scaffolding it", crate_name);
+                Ok(Err(crate_name))
+            }
+            None => Err(anyhow::anyhow!(
+                "Failed to find crate {} on crates.io, pass --template to scaffold a new one",
+                crate_name
+            )),
+        }
+    }
+
+    fn set_repo_url(&mut self, repo_url: &str) -> Result<(), anyhow::Error> {
+        self.state.repo_url = Some(repo_url.to_string());
+        let repo_name = repo_url.split('/').next_back().ok_or_else(|| {
             anyhow::anyhow!("Repository URL does not contain a name: {}", repo_url)
         })?;
-        self.repo_name = Some(repo_name.to_string());
-        Ok(repo_url)
+        self.state.repo_name = Some(repo_name.to_string());
+        Ok(())
+    }
+
+    /// Creates the crate in the work directory and continues the run in it
+    /// as a local checkout without a remote.
+    async fn scaffold_repository(&mut self, crate_name: &str) -> Result<(), anyhow::Error> {
+        let crate_dir = self.work_dir.join(crate_name);
+        scaffold::scaffold(
+            crate_name,
+            &crate_dir,
+            self.target.template.as_deref(),
+            &self.state.instructions,
+        )
+        .await?;
+        tokio::fs::write(crate_dir.join(".gitignore"), &self.git.gitignore).await?;
+        use_local_checkout(&mut self.state, &crate_dir)?;
+        self.state.save(&self.work_dir)?;
+
+        let status = Command::new("git")
+            .args(["add", "--all"])
+            .current_dir(&crate_dir)
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to add the scaffolded files"));
+        }
+        let status = Command::new("git")
+            .arg("commit")
+            .arg("-m")
+            .arg(format!("Scaffold {}", crate_name))
+            .arg("--author")
+            .arg(&self.git.commit_author)
+            .arg("--no-verify")
+            .current_dir(&crate_dir)
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to commit the scaffolded crate"));
+        }
+        Ok(())
     }
 
     async fn prepare_repository(&mut self, repo_url: String) -> Result<(), anyhow::Error> {
-        let repo_dir = self.work_dir.join(
-            self.repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-        );
-        self.github
+        let repo_dir = self.repo_dir()?;
+        self.code_host
             .fork_and_clone(&repo_url, &self.work_dir)
             .await?;
-        self.flake = Some(Flake::new(
-            &self
-                .repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-            &repo_dir,
-        ));
 
         // Modify .gitignore file
         info!("Modifying .gitignore file...");
         let gitignore_path = repo_dir.join(".gitignore");
-        let gitignore_contents = "/target\n/result\n/work_dir\n/db\n/tmp\n/nix\n/result\n";
         if !gitignore_path.exists() {
             tokio::fs::File::create(&gitignore_path).await?;
         }
-        tokio::fs::write(&gitignore_path, gitignore_contents).await?;
+        tokio::fs::write(&gitignore_path, &self.git.gitignore).await?;
         Ok(())
     }
 
     async fn commit_changes(&self, main_diff: bool) -> Result<(), anyhow::Error> {
         info!("Staging changes...");
-        let repo_dir = self.work_dir.join(
-            self.repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-        );
+        let repo_dir = self.repo_dir()?;
 
         let status = Command::new("git")
             .arg("add")
@@ -204,12 +789,26 @@ impl App {
             return Err(anyhow::anyhow!("Failed to add changes"));
         }
 
+        let unchanged = Command::new("git")
+            .arg("diff")
+            .arg("--cached")
+            .arg("--quiet")
+            .current_dir(&repo_dir)
+            .status()
+            .await?
+            .success();
+        if unchanged {
+            info!("Nothing to commit");
+            return Ok(());
+        }
+
         // Generate the commit message
         let mut git_diff_command = {
             let mut cmd = Command::new("git");
-            cmd.arg("diff").arg("--cached");
+            cmd.arg("diff").arg("--cached").current_dir(&repo_dir);
             if main_diff {
-                cmd.arg("src/main.rs"); // Targeting only main.rs
+                // Only the entry point the LLM rewrites
+                cmd.arg(self.entry_source_path().await?);
             }
             cmd
         };
@@ -217,7 +816,7 @@ impl App {
         let git_diff = git_diff_command.output().await?.stdout;
         let git_diff_str = String::from_utf8(git_diff)?;
         info!("Git diff: {}", git_diff_str);
-        let commit_message = self.groq.generate_commit_message(&git_diff_str).await?;
+        let commit_message = self.llm.generate_commit_message(&git_diff_str).await?;
 
         info!("Committing changes...");
         let status = Command::new("git")
@@ -225,7 +824,7 @@ impl App {
             .arg("-m")
             .arg(commit_message)
             .arg("--author")
-            .arg("FlakeBot <flakebot@flakebot.com>")
+            .arg(&self.git.commit_author)
             .arg("--no-verify")
             .current_dir(&repo_dir)
             .status()
@@ -238,60 +837,79 @@ impl App {
         Ok(())
     }
 
+    /// The entry point of the first selected binary as reported by `cargo
+    /// metadata`, or of the library for crates without binaries.
+    async fn entry_source_path(&self) -> Result<PathBuf, anyhow::Error> {
+        match self.state.binaries.first() {
+            Some(binary) => Ok(binary.src_path.clone()),
+            None => cargo::library_source_path(&self.repo_dir()?).await,
+        }
+    }
+
     pub async fn push_changes(&self, main_diff: bool) -> Result<(), anyhow::Error> {
         self.commit_changes(main_diff).await?;
-        let repo_dir = self.work_dir.join(
-            self.repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-        );
-        self.github.push_changes(&repo_dir).await?;
-        Ok(())
+        self.push().await
     }
 
-    async fn process_repository_files(&self) -> Result<(), anyhow::Error> {
-        let repo_dir = self.work_dir.join(
-            self.repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-        );
-        let _ = std::fs::read_to_string(
-            &self
-                .flake
-                .as_ref()
-                .ok_or(anyhow::anyhow!("Failed to get flake"))?
-                .flake_path,
-        )?;
-        let _ = std::fs::read_to_string(&repo_dir.join("Cargo.toml"))?;
-        let _ = std::fs::read_to_string(&repo_dir.join("README.md"))?;
-        let _ = std::fs::read_to_string(&repo_dir.join("src/main.rs"))?;
+    async fn push(&self) -> Result<(), anyhow::Error> {
+        let repo_dir = self.repo_dir()?;
+        if !has_origin(&repo_dir) {
+            info!("No origin remote, keeping the changes local");
+            return Ok(());
+        }
+        self.code_host.push_changes(&repo_dir).await?;
         Ok(())
     }
 
+    /// Opens a pull request for the current branch, unless the repository
+    /// has nowhere to open it such as a scaffolded crate.
+    async fn open_pull_request(&self, extra_body: Option<&str>) -> Result<(), anyhow::Error> {
+        let repo_dir = self.repo_dir()?;
+        if !has_origin(&repo_dir) {
+            info!("No origin remote, not opening a pull request");
+            return Ok(());
+        }
+        self.code_host
+            .open_pull_request(&repo_dir, self.llm.as_ref(), extra_body)
+            .await
+    }
+
     async fn update_and_write_flake(&mut self) -> Result<(), anyhow::Error> {
-        let repo_dir = self.work_dir.join(
-            self.repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-        );
-        let binary_name = self
-            .repo_name
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?;
-        let cargo_toml_contents = std::fs::read_to_string(&repo_dir.join("Cargo.toml"))?;
-        let readme_contents = std::fs::read_to_string(&repo_dir.join("README.md"))?;
-        let main_rs_contents = std::fs::read_to_string(&repo_dir.join("src/main.rs"))?;
+        let repo_dir = self.repo_dir()?;
+        let targets = cargo::binary_targets(&repo_dir).await?;
+        self.state.binaries = cargo::select_binaries(&targets, &self.requested_binaries)?;
+        // Libraries have no binaries, name the flake package after the crate
+        let flake_targets = if self.state.binaries.is_empty() {
+            let package = cargo::package_names(&repo_dir)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Repository has no packages"))?;
+            vec![BinaryTarget {
+                package: package.clone(),
+                name: package,
+                src_path: cargo::library_source_path(&repo_dir).await?,
+            }]
+        } else {
+            self.state.binaries.clone()
+        };
+        let cargo_toml_contents = std::fs::read_to_string(repo_dir.join("Cargo.toml"))?;
+        // Not every crate has a README, the description then comes from the code
+        let readme_contents = match std::fs::read_to_string(repo_dir.join("README.md")) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            readme_contents => readme_contents?,
+        };
+        let main_rs_contents = std::fs::read_to_string(self.entry_source_path().await?)?;
 
         let crate_description = self
-            .groq
+            .llm
             .create_crate_description(&cargo_toml_contents, &readme_contents, &main_rs_contents)
             .await?;
 
-        self.flake
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get flake"))?
-            .write_description_and_binary_name(&crate_description, &binary_name)
+        self.flake()?
+            .write_description_and_binaries(&crate_description, &flake_targets)
             .await?;
+        self.state.crate_description = Some(crate_description);
 
         Ok(())
     }
@@ -299,13 +917,14 @@ impl App {
     async fn validate_and_check_program(&self, repo_dir: PathBuf) -> Result<bool, anyhow::Error> {
         // Run cargo check initially
 
-        let initial_main_rs_contents = std::fs::read_to_string(&repo_dir.join("src/main.rs"))?;
+        let main_rs_path = self.entry_source_path().await?;
+        let initial_main_rs_contents = std::fs::read_to_string(&main_rs_path)?;
         let mut main_rs_contents = initial_main_rs_contents.clone();
 
         loop {
             let instructions = self
-                .groq
-                .validate_binary(&self.instructions, &main_rs_contents)
+                .llm
+                .validate_binary(&self.state.instructions, &main_rs_contents)
                 .await?;
 
             let first_word = instructions.split_whitespace().next().unwrap_or("");
@@ -318,7 +937,7 @@ impl App {
                 _ => {
                     info!("Program does not satisfy user instructions, rewriting code");
                     main_rs_contents = self
-                        .write_code(instructions, repo_dir.join("src/main.rs"), &repo_dir)
+                        .write_code(instructions, main_rs_path.clone(), &repo_dir)
                         .await?;
 
                     // Attempt to run cargo check after each rewrite
@@ -326,7 +945,7 @@ impl App {
                     if check_result.is_err() {
                         error!("Cargo check failed, retrying with incremental fixes...");
                         main_rs_contents = initial_main_rs_contents.clone();
-                        std::fs::write(&repo_dir.join("src/main.rs"), &main_rs_contents)?;
+                        std::fs::write(&main_rs_path, &main_rs_contents)?;
                     }
                 }
             }
@@ -355,19 +974,19 @@ impl App {
         &self,
         instructions: String,
         main_rs_path: PathBuf,
-        repo_dir: &PathBuf,
+        repo_dir: &Path,
     ) -> Result<String, anyhow::Error> {
         let main_rs_contents = std::fs::read_to_string(&main_rs_path)?;
 
         let new_contents = self
-            .groq
+            .llm
             .rewrite_main_rs(&instructions, &main_rs_contents)
             .await?;
 
         // add cargo deps
         let _ = self
-            .groq
-            .add_cargo_deps(&new_contents, &repo_dir)
+            .llm
+            .add_cargo_deps(&new_contents, repo_dir)
             .await
             .map_err(|e| {
                 error!("Failed to add cargo dependencies: {}", e);
@@ -385,102 +1004,306 @@ impl App {
         Ok(cleaned_contents)
     }
 
-    async fn build_and_output_binary(&self) -> Result<PathBuf, anyhow::Error> {
-        let repo_dir = self.work_dir.join(
-            self.repo_name
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
+    async fn build_and_output_binary(&self) -> Result<BuildRecord, anyhow::Error> {
+        let repo_dir = self.repo_dir()?;
+        info!(
+            "Building the tool with the {} builder...",
+            self.builder.name()
         );
-        let output_path = repo_dir.join("result"); // This is where nix-build outputs the binary by default
-
-        info!("Building the tool using flake.nix...");
-        let crate_name = self
-            .repo_name
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?;
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(format!(
-                "cd {} && nix build && sha256sum ./result/bin/{}",
-                repo_dir.display(),
-                crate_name
-            ))
-            .output()
-            .await?;
-        info!("nix-build output sha256sum: {:?}", output);
+        let source_rev = head_rev(&repo_dir)?;
+        let build_output = self.builder.build(&repo_dir, &self.state.binaries).await?;
+
+        let bin_dir = self.work_dir.join("bin");
+        std::fs::create_dir_all(&bin_dir)?;
+        let mut binaries = Vec::new();
+        for binary in &self.state.binaries {
+            let built_path = build_output.bin_dir.join(&binary.name);
+            let sha256 = attestation::sha256_file(&built_path)?;
+            info!("bin/{} sha256: {}", binary.name, sha256);
+            binaries.push(BinaryDigest {
+                name: format!("bin/{}", binary.name),
+                sha256,
+            });
 
-        if !output.status.success() {
-            let errors = String::from_utf8_lossy(&output.stderr);
-            error!("nix-build failed: {}", errors);
-            return Err(anyhow::anyhow!("nix-build failed: {}", errors));
+            let output_path = bin_dir.join(&binary.name);
+            // Store paths are read-only, remove any copy left by a previous run
+            if output_path.exists() {
+                std::fs::remove_file(&output_path)?;
+            }
+            std::fs::copy(&built_path, &output_path)?;
+            std::fs::set_permissions(&output_path, std::fs::Permissions::from_mode(0o755))?;
         }
 
-        info!("Build successful, binary located at {:?}", output_path);
+        let build_record = BuildRecord {
+            builder: self.builder.name().to_string(),
+            source_url: self.state.repo_url.clone().unwrap_or_default(),
+            source_rev,
+            store_path: build_output.store_path,
+            nar_hash: build_output.nar_hash,
+            reproducible: build_output.reproducible,
+            binaries,
+            locked_inputs: build_output.locked_inputs,
+        };
+
+        info!("Binaries copied to {}", bin_dir.display());
 
-        // Optionally, you can copy the binary to a specific location
-        let desired_output_path = self.work_dir.join("final_binary");
-        std::fs::copy(
-            output_path.join("bin").join(
-                self.repo_name
-                    .as_ref()
-                    .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-            ),
-            &desired_output_path,
-        )?;
+        Ok(build_record)
+    }
 
-        // Set the binary as executable
-        std::fs::set_permissions(&desired_output_path, std::fs::Permissions::from_mode(0o755))?;
+    /// Signs the provenance for a build and writes it next to the final binary.
+    fn write_attestation(&self, build_record: &BuildRecord) -> Result<PathBuf, anyhow::Error> {
+        let statement = attestation::provenance_statement(build_record);
+        let signing_key = attestation::load_or_create_signing_key(&self.attestation_key)?;
+        let envelope = attestation::sign_statement(&statement, &signing_key)?;
 
-        Ok(desired_output_path)
+        let attestation_path = self.attestation_path();
+        std::fs::write(&attestation_path, serde_json::to_string_pretty(&envelope)?)?;
+        info!(
+            "Build attestation written to {}",
+            attestation_path.display()
+        );
+        Ok(attestation_path)
     }
 
-    pub async fn run_binary(&self, binary_path: &PathBuf) -> Result<(), anyhow::Error> {
-        info!("Making sure the binary is executable...");
-        let output = Command::new("chmod")
-            .arg("+x")
-            .arg(binary_path)
-            .output()
-            .await?;
+    /// Points the attestation at the current HEAD. Commits made since the
+    /// build may only add files the build does not read, such as the OpenAPI
+    /// document, otherwise the tool has to be rebuilt.
+    fn attest_head(&self, build_record: &mut BuildRecord) -> Result<(), anyhow::Error> {
+        let repo_dir = self.repo_dir()?;
+        let head = head_rev(&repo_dir)?;
+        if head == build_record.source_rev {
+            return Ok(());
+        }
+        let changed = changed_paths(&repo_dir, &build_record.source_rev, &head)?;
+        if let Some(path) = changed.iter().find(|path| *path != OPENAPI_FILE_NAME) {
+            return Err(anyhow::anyhow!(
+                "{} changed since the build, run the build stage again",
+                path
+            ));
+        }
+        build_record.source_rev = head;
+        self.write_attestation(build_record)?;
+        Ok(())
+    }
 
-        if !output.status.success() {
-            let errors = String::from_utf8_lossy(&output.stderr);
-            error!("Failed to make binary executable: {}", errors);
+    /// Decides how the built tool is exercised: crates without binaries are
+    /// libraries, otherwise the configured kind or the LLM's classification.
+    async fn classify_tool(&self) -> Result<ToolKind, anyhow::Error> {
+        let kind = match self.tool_kind {
+            Some(kind) => kind,
+            None if self.state.binaries.is_empty() => ToolKind::Library,
+            None => {
+                let main_rs_path = self.entry_source_path().await?;
+                self.llm.classify_tool(&main_rs_path).await?
+            }
+        };
+        info!("Tool classified as {:?}", kind);
+        Ok(kind)
+    }
+
+    /// Runs the harness matching the tool kind and writes the report to the
+    /// work directory, failing if any check did not pass.
+    pub async fn smoke_test(
+        &self,
+        kind: ToolKind,
+        binary_path: Option<&Path>,
+    ) -> Result<SmokeReport, anyhow::Error> {
+        self.print_interaction_instructions(kind).await?;
+
+        let repo_dir = self.repo_dir()?;
+        let main_rs_path = self.entry_source_path().await?;
+        let binary_path =
+            || binary_path.ok_or_else(|| anyhow::anyhow!("No binary built to smoke test"));
+        let report = match kind {
+            ToolKind::HttpServer => {
+                let plan = self.llm.get_smoke_test_plan(&main_rs_path).await?;
+                smoke::run_http_smoke_tests(binary_path()?, &plan, self.smoke_startup_timeout)
+                    .await?
+            }
+            ToolKind::Cli => {
+                let binary_name = self
+                    .state
+                    .binaries
+                    .first()
+                    .map(|binary| binary.name.as_str())
+                    .unwrap_or_default();
+                let plan = self
+                    .llm
+                    .get_cli_test_plan(&main_rs_path, binary_name)
+                    .await?;
+                smoke::run_cli_smoke_tests(binary_path()?, &plan).await?
+            }
+            ToolKind::Library => smoke::run_doc_tests(&repo_dir, smoke::DOC_TEST_TIMEOUT).await?,
+            ToolKind::Daemon => {
+                smoke::run_daemon_smoke_test(binary_path()?, self.daemon_uptime).await?
+            }
+        };
+
+        let report_path = self.work_dir.join("smoke_report.json");
+        std::fs::write(&report_path, serde_json::to_string_pretty(&report)?)?;
+        info!("Smoke test report written to {}", report_path.display());
+
+        if !report.passed {
+            error!("Smoke tests failed:\n{}", report.summary());
             return Err(anyhow::anyhow!(
-                "Failed to make binary executable: {}",
-                errors
+                "Smoke tests failed, see {}",
+                report_path.display()
             ));
         }
+        info!("Smoke tests passed");
+        Ok(report)
+    }
 
-        info!("Running the binary...");
-        let mut child = Command::new(binary_path).spawn()?;
+    /// Writes the OpenAPI document of an HTTP tool into the repository so it
+    /// is committed with the feature. The document served at `/openapi.json`
+    /// is preferred, otherwise one is derived from the router by the LLM.
+    /// Returns the path relative to the repository.
+    async fn write_openapi_spec(
+        &self,
+        smoke_report: &SmokeReport,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        let repo_dir = self.repo_dir()?;
+        let spec = match &smoke_report.openapi {
+            Some(spec) => {
+                info!("Using the OpenAPI document served by the tool");
+                spec.clone()
+            }
+            None => {
+                info!("Tool does not serve /openapi.json, deriving the OpenAPI document");
+                self.llm
+                    .generate_openapi_spec(&self.entry_source_path().await?)
+                    .await?
+            }
+        };
+        if spec.get("openapi").and_then(|v| v.as_str()).is_none() {
+            return Err(anyhow::anyhow!("Generated OpenAPI document has no version"));
+        }
 
-        info!("Binary started successfully, server running in a separate process...");
+        std::fs::write(
+            repo_dir.join(OPENAPI_FILE_NAME),
+            serde_json::to_string_pretty(&spec)?,
+        )?;
+        info!("OpenAPI document written to {}", OPENAPI_FILE_NAME);
+        Ok(spec)
+    }
 
-        // Print interaction instructions
-        self.print_interaction_instructions().await?;
+    /// Writes the tool manifest for agent runtimes into the work directory,
+    /// locked to the revision that was pushed.
+    async fn write_manifest(
+        &self,
+        tool_kind: ToolKind,
+        build_record: &BuildRecord,
+        smoke_report: &SmokeReport,
+        openapi: Option<serde_json::Value>,
+    ) -> Result<ToolManifest, anyhow::Error> {
+        let repo_dir = self.repo_dir()?;
+        let repo_name = self
+            .state
+            .repo_name
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?;
+        let repository = git2::Repository::open(&repo_dir)?;
+        let rev = &build_record.source_rev;
+        // Scaffolded crates are only available locally
+        let remote_url = match repository.find_remote("origin") {
+            Ok(remote) => remote
+                .url()
+                .ok_or_else(|| anyhow::anyhow!("Remote origin has no URL"))?
+                .to_string(),
+            Err(_) => format!("file://{}", repo_dir.display()),
+        };
+        let package = match self.state.binaries.first() {
+            Some(binary) => binary.package.clone(),
+            None => cargo::package_names(&repo_dir)
+                .await?
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| repo_name.clone()),
+        };
+        let version = cargo::package_version(&repo_dir, &package).await?;
+
+        let binary = self
+            .state
+            .binaries
+            .first()
+            .map(|binary| binary.name.clone())
+            .ok_or_else(|| anyhow::anyhow!("No binary built"));
+        let invocation = match tool_kind {
+            ToolKind::Cli => {
+                let binary = binary?;
+                let args_schema = self
+                    .llm
+                    .generate_cli_args_schema(&self.entry_source_path().await?, &binary)
+                    .await?;
+                Invocation::Cli {
+                    binary,
+                    args_schema,
+                }
+            }
+            ToolKind::HttpServer => Invocation::Http {
+                binary: binary?,
+                port: smoke_report.port,
+                openapi: openapi
+                    .ok_or_else(|| anyhow::anyhow!("HTTP tool has no OpenAPI document"))?,
+            },
+            ToolKind::Daemon => Invocation::Daemon { binary: binary? },
+            ToolKind::Library => Invocation::Library {
+                crate_name: package,
+            },
+        };
 
-        // Execute curl commands loop
-        self.execute_curl_commands().await?;
+        let manifest = ToolManifest {
+            schema_version: manifest::MANIFEST_SCHEMA_VERSION,
+            name: repo_name,
+            version,
+            description: self.state.crate_description.clone(),
+            flake: FlakeReference::locked(&remote_url, rev),
+            builder: build_record.builder.clone(),
+            binaries: build_record
+                .binaries
+                .iter()
+                .map(|binary| manifest::ManifestBinary {
+                    name: binary.name.clone(),
+                    sha256: binary.sha256.clone(),
+                })
+                .collect(),
+            invocation,
+            environment: manifest::scan_source_dir(&repo_dir.join("src"))?,
+            tests: smoke_report.into(),
+        };
 
-        // Kill the process after finishing with curl commands
-        child.kill().await?;
-        info!("Process killed successfully.");
+        let manifest_path = self.work_dir.join(manifest::MANIFEST_FILE_NAME);
+        manifest.write(&manifest_path)?;
+        Ok(manifest)
+    }
 
+    /// Adds the built tool to the local registry so other agents on the host
+    /// can find it without rebuilding.
+    fn register_tool(
+        &self,
+        manifest: &ToolManifest,
+        attestation_path: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let mut registry = Registry::open(&self.registry_dir)?;
+        let entry =
+            registry.register(manifest, &self.work_dir.join("bin"), Some(attestation_path))?;
+        info!(
+            "Registered {} {} in {}",
+            entry.name,
+            entry.version,
+            self.registry_dir.display()
+        );
         Ok(())
     }
 
-    pub async fn print_interaction_instructions(&self) -> Result<(), anyhow::Error> {
-        let main_rs_path = self
-            .work_dir
-            .join(
-                self.repo_name
-                    .as_ref()
-                    .ok_or_else(|| anyhow::anyhow!("Failed to get repository name"))?,
-            )
-            .join("src/main.rs");
+    pub async fn print_interaction_instructions(
+        &self,
+        kind: ToolKind,
+    ) -> Result<(), anyhow::Error> {
+        let source_path = self.entry_source_path().await?;
         let instructions = self
-            .groq
-            .get_interaction_instructions(&main_rs_path)
+            .llm
+            .get_interaction_instructions(&source_path, kind)
             .await?;
 
         info!(
@@ -494,32 +1317,25 @@ impl App {
         Ok(())
     }
 
-    pub async fn execute_curl_commands(&self) -> Result<(), anyhow::Error> {
-        let mut reader = BufReader::new(io::stdin());
-        let mut line = String::new();
-
-        println!("Enter curl commands, type 'done' to exit:");
-
-        while reader.read_line(&mut line).await? != 0 {
-            let command = line.trim();
-            if command == "done" {
-                break;
-            }
-
-            let output = Command::new("sh").arg("-c").arg(command).output().await?;
-
-            if output.status.success() {
-                let response = String::from_utf8_lossy(&output.stdout);
-                println!("Response: {}", response);
-            } else {
-                let error_message = String::from_utf8_lossy(&output.stderr);
-                println!("Error: {}", error_message);
-            }
-
-            line.clear(); // Clear the line buffer for the next input
+    fn repo_dir(&self) -> Result<PathBuf, anyhow::Error> {
+        if let Some(repo_path) = &self.state.repo_path {
+            return Ok(repo_path.clone());
         }
+        Ok(self
+            .work_dir
+            .join(self.state.repo_name.as_ref().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No repository cloned yet, run the flake stage first or pass --repo-path"
+                )
+            })?))
+    }
 
-        Ok(())
+    fn flake(&self) -> Result<Flake, anyhow::Error> {
+        Ok(Flake::new(&self.repo_dir()?))
+    }
+
+    fn attestation_path(&self) -> PathBuf {
+        self.work_dir.join("attestation.intoto.json")
     }
 
     pub async fn install_flakebox_files(&self, repo_dir: &PathBuf) -> Result<(), anyhow::Error> {
@@ -528,19 +1344,19 @@ impl App {
         // List of directories to copy from this level into the repo dir
         let directories_to_copy = vec![".config", ".github", "misc"];
         for dir in directories_to_copy {
-            let source = PathBuf::from(format!("{}", dir));
+            let source = PathBuf::from(dir);
             let destination = repo_dir;
             tokio::fs::create_dir_all(&destination).await?;
             // Assuming recursive copy is needed
             let mut options = CopyOptions::new(); // Initialize default options
             options.copy_inside = true; // To copy the contents into the destination
-            dir::copy(&source, &destination, &options)?;
+            dir::copy(&source, destination, &options)?;
         }
 
         // List of files to copy
         let files_to_copy = vec!["justfile"];
         for file in files_to_copy {
-            let source = PathBuf::from(format!("{}", file));
+            let source = PathBuf::from(file);
             let destination = repo_dir.join(file);
             // create the file if it doesn't exist
             if !destination.exists() {
@@ -552,3 +1368,133 @@ impl App {
         Ok(())
     }
 }
+
+/// The URL of a git remote given as a URL or in the scp-like
+/// `user@host:path` syntax, which is normalized to `ssh://user@host/path` so
+/// it can be used in flake references. `None` for local paths.
+pub fn git_url(repo: &str) -> Option<String> {
+    if repo.contains("://") {
+        return Some(repo.to_string());
+    }
+    let (host, path) = repo.split_once(':')?;
+    // Like git, only treat it as a host when there is no slash before the
+    // colon, and not when it is a Windows drive letter
+    if host.len() < 2 || host.contains(['/', '\\']) || path.is_empty() {
+        return None;
+    }
+    Some(format!("ssh://{}/{}", host, path.trim_start_matches('/')))
+}
+
+/// Points the run at the git checkout in `repo_path`, named after its
+/// directory, with its `origin` remote as the repository URL.
+fn use_local_checkout(state: &mut RunState, repo_path: &Path) -> Result<(), anyhow::Error> {
+    let repo_path = repo_path.canonicalize().map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to open repository at {}: {}",
+            repo_path.display(),
+            e
+        )
+    })?;
+    let repository = git2::Repository::open(&repo_path)
+        .map_err(|e| anyhow::anyhow!("{} is not a git repository: {}", repo_path.display(), e))?;
+    if !repo_path.join("Cargo.toml").exists() {
+        return Err(anyhow::anyhow!("{} has no Cargo.toml", repo_path.display()));
+    }
+    let repo_name = repo_path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Repository path has no name: {}", repo_path.display()))?
+        .to_string_lossy()
+        .to_string();
+    state.repo_url = repository
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().map(str::to_string));
+    state.repo_name = Some(repo_name);
+    state.repo_path = Some(repo_path);
+    Ok(())
+}
+
+fn head_rev(repo_dir: &Path) -> Result<String, anyhow::Error> {
+    Ok(git2::Repository::open(repo_dir)?
+        .head()?
+        .peel_to_commit()?
+        .id()
+        .to_string())
+}
+
+/// The paths that differ between the trees of two commits.
+fn changed_paths(repo_dir: &Path, from: &str, to: &str) -> Result<Vec<String>, anyhow::Error> {
+    let repository = git2::Repository::open(repo_dir)?;
+    let tree = |rev: &str| -> Result<git2::Tree<'_>, anyhow::Error> {
+        Ok(repository.find_commit(git2::Oid::from_str(rev)?)?.tree()?)
+    };
+    let diff = repository.diff_tree_to_tree(Some(&tree(from)?), Some(&tree(to)?), None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+fn has_origin(repo_dir: &Path) -> bool {
+    git2::Repository::open(repo_dir)
+        .and_then(|repository| repository.find_remote("origin").map(|_| ()))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_defaults_to_every_stage() {
+        assert_eq!(Stage::select(&[], &[]), Stage::ALL.to_vec());
+        assert_eq!(
+            Stage::select(&[], &[Stage::Flakebox, Stage::Pr]),
+            vec![Stage::Flake, Stage::Feature, Stage::Build, Stage::Verify]
+        );
+    }
+
+    #[test]
+    fn select_applies_skip_to_the_given_stages_in_pipeline_order() {
+        assert_eq!(
+            Stage::select(
+                &[Stage::Pr, Stage::Verify, Stage::Build],
+                &[Stage::Verify, Stage::Flake]
+            ),
+            vec![Stage::Build, Stage::Pr]
+        );
+    }
+
+    #[test]
+    fn select_can_be_empty() {
+        assert!(Stage::select(&[Stage::Build], &[Stage::Build]).is_empty());
+        assert!(Stage::select(&[], &Stage::ALL).is_empty());
+    }
+
+    #[test]
+    fn git_urls_are_told_apart_from_local_paths() {
+        for (repo, expected) in [
+            (
+                "https://github.com/owner/repo",
+                Some("https://github.com/owner/repo"),
+            ),
+            (
+                "ssh://git@github.com/owner/repo.git",
+                Some("ssh://git@github.com/owner/repo.git"),
+            ),
+            (
+                "git@github.com:owner/repo.git",
+                Some("ssh://git@github.com/owner/repo.git"),
+            ),
+            ("host:/srv/repo", Some("ssh://host/srv/repo")),
+            ("/home/user/repo", None),
+            ("../repo", None),
+            ("./dir:with/colon", None),
+            ("C:\\repo", None),
+            ("host:", None),
+        ] {
+            assert_eq!(git_url(repo).as_deref(), expected, "{}", repo);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write as _;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::flake::LockedInput;
+
+pub const IN_TOTO_STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const IN_TOTO_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+pub const SLSA_PROVENANCE_TYPE: &str = "https://slsa.dev/provenance/v1";
+pub const FLAKEBOT_BUILDER_ID: &str = "https://github.com/kodylow/deterministic_program_crafter";
+
+/// A binary produced by the build, identified by its path relative to the
+/// store output (e.g. `bin/ripgrep`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryDigest {
+    pub name: String,
+    pub sha256: String,
+}
+
+/// Everything recorded about a build for the provenance statement.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    /// Name of the builder that produced the binaries
+    pub builder: String,
+    pub source_url: String,
+    pub source_rev: String,
+    pub store_path: Option<String>,
+    pub nar_hash: Option<String>,
+    pub reproducible: bool,
+    pub binaries: Vec<BinaryDigest>,
+    pub locked_inputs: Vec<LockedInput>,
+}
+
+/// A DSSE envelope carrying a signed in-toto statement.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub payload_type: String,
+    pub payload: String,
+    pub signatures: Vec<EnvelopeSignature>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EnvelopeSignature {
+    pub keyid: String,
+    pub sig: String,
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, anyhow::Error> {
+    let contents = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    Ok(hex::encode(Sha256::digest(contents)))
+}
+
+/// Builds an in-toto statement with a SLSA provenance predicate for the
+/// build record.
+pub fn provenance_statement(record: &BuildRecord) -> serde_json::Value {
+    let subject: Vec<_> = record
+        .binaries
+        .iter()
+        .map(|binary| json!({ "name": binary.name, "digest": { "sha256": binary.sha256 } }))
+        .collect();
+    let resolved_dependencies: Vec<_> = record
+        .locked_inputs
+        .iter()
+        .map(|input| {
+            let mut digest = json!({ "nixNarHash": input.nar_hash });
+            if let Some(rev) = &input.rev {
+                digest["gitCommit"] = json!(rev);
+            }
+            json!({ "name": input.name, "uri": input.source, "digest": digest })
+        })
+        .collect();
+    let byproducts: Vec<_> = record
+        .store_path
+        .iter()
+        .zip(&record.nar_hash)
+        .map(|(store_path, nar_hash)| {
+            json!({
+                "name": "nix-store-path",
+                "uri": store_path,
+                "digest": { "nixNarHash": nar_hash },
+            })
+        })
+        .collect();
+
+    json!({
+        "_type": IN_TOTO_STATEMENT_TYPE,
+        "subject": subject,
+        "predicateType": SLSA_PROVENANCE_TYPE,
+        "predicate": {
+            "buildDefinition": {
+                "buildType": format!("{}/{}-build/v1", FLAKEBOT_BUILDER_ID, record.builder),
+                "externalParameters": {
+                    "source": { "uri": record.source_url, "digest": { "gitCommit": record.source_rev } },
+                },
+                "internalParameters": {
+                    "builder": record.builder,
+                    "reproducibilityVerified": record.reproducible,
+                },
+                "resolvedDependencies": resolved_dependencies,
+            },
+            "runDetails": {
+                "builder": { "id": FLAKEBOT_BUILDER_ID },
+                "byproducts": byproducts,
+            },
+        },
+    })
+}
+
+/// Loads the hex-encoded ed25519 seed at `key_path`, generating and saving a
+/// new key if none exists yet, and writes its `.pub` counterpart if missing.
+/// The seed is only readable by its owner and an existing key is never
+/// overwritten.
+pub fn load_or_create_signing_key(key_path: &Path) -> Result<SigningKey, anyhow::Error> {
+    let signing_key = if key_path.exists() {
+        read_signing_key(key_path)?
+    } else {
+        create_signing_key(key_path)?
+    };
+    write_verifying_key(key_path, &signing_key)?;
+    Ok(signing_key)
+}
+
+fn create_signing_key(key_path: &Path) -> Result<SigningKey, anyhow::Error> {
+    info!("Generating attestation key at {}", key_path.display());
+    let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut key_file = match options.open(key_path) {
+        Ok(key_file) => key_file,
+        // Another run created the key since we looked, use that one
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return read_signing_key(key_path)
+        }
+        Err(e) => {
+            return Err(anyhow::anyhow!(
+                "Failed to create {}: {}",
+                key_path.display(),
+                e
+            ))
+        }
+    };
+    key_file.write_all(hex::encode(signing_key.to_bytes()).as_bytes())?;
+    key_file.sync_all()?;
+    Ok(signing_key)
+}
+
+/// Derives the `.pub` file next to `key_path` from the seed, so a run that
+/// stopped after saving the seed, or a seed supplied without one, still
+/// gets it.
+fn write_verifying_key(key_path: &Path, signing_key: &SigningKey) -> Result<(), anyhow::Error> {
+    let pub_path = key_path.with_extension("pub");
+    let verifying_key = hex::encode(signing_key.verifying_key().to_bytes());
+    match std::fs::read_to_string(&pub_path) {
+        Ok(contents) if contents.trim() == verifying_key => Ok(()),
+        _ => std::fs::write(&pub_path, verifying_key)
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", pub_path.display(), e)),
+    }
+}
+
+fn read_signing_key(key_path: &Path) -> Result<SigningKey, anyhow::Error> {
+    let contents = std::fs::read_to_string(key_path)?;
+    let seed: [u8; 32] = hex::decode(contents.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Attestation key must be a 32 byte hex seed"))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// The DSSE pre-authentication encoding that gets signed.
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut encoded = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    encoded.extend_from_slice(payload);
+    encoded
+}
+
+pub fn sign_statement(
+    statement: &serde_json::Value,
+    signing_key: &SigningKey,
+) -> Result<Envelope, anyhow::Error> {
+    let payload = serde_json::to_vec(statement)?;
+    let signature = signing_key.sign(&pae(IN_TOTO_PAYLOAD_TYPE, &payload));
+    let keyid = hex::encode(Sha256::digest(signing_key.verifying_key().to_bytes()));
+    Ok(Envelope {
+        payload_type: IN_TOTO_PAYLOAD_TYPE.to_string(),
+        payload: BASE64.encode(payload),
+        signatures: vec![EnvelopeSignature {
+            keyid,
+            sig: BASE64.encode(signature.to_bytes()),
+        }],
+    })
+}
+
+/// Renders the build hashes as a markdown section for a pull request body.
+pub fn attestation_summary(record: &BuildRecord) -> String {
+    let mut summary = format!(
+        "### Build attestation\n\n- Builder: `{}`\n- Source revision: `{}`\n",
+        record.builder, record.source_rev
+    );
+    if let (Some(store_path), Some(nar_hash)) = (&record.store_path, &record.nar_hash) {
+        let _ = writeln!(summary, "- Store path: `{}`", store_path);
+        let _ = writeln!(summary, "- NAR hash: `{}`", nar_hash);
+    }
+    if record.reproducible {
+        summary.push_str("\nReproducibility verified with `nix build --rebuild`.\n");
+    } else {
+        summary.push_str("\nReproducibility was not verified for this build.\n");
+    }
+    summary.push_str("\n| Binary | sha256 |\n|---|---|\n");
+    for binary in &record.binaries {
+        let _ = writeln!(summary, "| {} | `{}` |", binary.name, binary.sha256);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Verifier};
+
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn pae_follows_dsse() {
+        assert_eq!(
+            pae("application/example", b"hello world"),
+            b"DSSEv1 19 application/example 11 hello world"
+        );
+    }
+
+    #[test]
+    fn signed_statements_verify() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let statement = json!({ "_type": IN_TOTO_STATEMENT_TYPE, "subject": [] });
+        let envelope = sign_statement(&statement, &signing_key).unwrap();
+        assert_eq!(envelope.payload_type, IN_TOTO_PAYLOAD_TYPE);
+
+        let payload = BASE64.decode(&envelope.payload).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&payload).unwrap(),
+            statement
+        );
+        let [signature] = &envelope.signatures[..] else {
+            panic!("Expected one signature");
+        };
+        let verifying_key = signing_key.verifying_key();
+        assert_eq!(
+            signature.keyid,
+            hex::encode(Sha256::digest(verifying_key.to_bytes()))
+        );
+        let sig: [u8; 64] = BASE64.decode(&signature.sig).unwrap().try_into().unwrap();
+        let sig = Signature::from_bytes(&sig);
+        verifying_key
+            .verify(&pae(IN_TOTO_PAYLOAD_TYPE, &payload), &sig)
+            .unwrap();
+        assert!(verifying_key
+            .verify(&pae(IN_TOTO_PAYLOAD_TYPE, b"tampered"), &sig)
+            .is_err());
+    }
+
+    #[test]
+    fn signing_keys_are_created_once_and_private() {
+        let dir = test_dir("signing-key");
+        let key_path = dir.join("attestation.key");
+        let created = load_or_create_signing_key(&key_path).unwrap();
+        let loaded = load_or_create_signing_key(&key_path).unwrap();
+        assert_eq!(created.to_bytes(), loaded.to_bytes());
+        assert_eq!(
+            std::fs::read_to_string(key_path.with_extension("pub")).unwrap(),
+            hex::encode(created.verifying_key().to_bytes())
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn missing_public_keys_are_derived_from_the_seed() {
+        let dir = test_dir("public-key");
+        let key_path = dir.join("attestation.key");
+        let pub_path = key_path.with_extension("pub");
+        let created = load_or_create_signing_key(&key_path).unwrap();
+        let verifying_key = hex::encode(created.verifying_key().to_bytes());
+
+        // As if the run stopped between writing the seed and the public key
+        std::fs::remove_file(&pub_path).unwrap();
+        load_or_create_signing_key(&key_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&pub_path).unwrap(), verifying_key);
+
+        std::fs::write(&pub_path, "trunc").unwrap();
+        load_or_create_signing_key(&key_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&pub_path).unwrap(), verifying_key);
+    }
+}
@@ -0,0 +1,223 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{error, info};
+
+use crate::cargo::{self, BinaryTarget};
+use crate::flake::{Flake, LockedInput};
+
+/// Rust toolchain pinned by the cargo builder when the repository has none.
+pub const DEFAULT_RUST_TOOLCHAIN: &str = "1.78.0";
+
+/// What a builder produced for the selected binaries.
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+    /// Directory containing the built binaries
+    pub bin_dir: PathBuf,
+    /// Nix store path of the build, only set by the nix builder
+    pub store_path: Option<String>,
+    /// NAR hash of the store path, only set by the nix builder
+    pub nar_hash: Option<String>,
+    /// Whether the build was rebuilt and compared bit-for-bit
+    pub reproducible: bool,
+    pub locked_inputs: Vec<LockedInput>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BuilderKind {
+    /// Use nix when it is installed, cargo otherwise
+    Auto,
+    Nix,
+    Cargo,
+}
+
+#[async_trait]
+pub trait Builder: Send + Sync {
+    /// Short name recorded in reports and attestations.
+    fn name(&self) -> &'static str;
+
+    /// Writes the files the build depends on into the repository, so they
+    /// are committed before the revision that gets attested is taken.
+    fn prepare(&self, _repo_dir: &Path) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    async fn build(
+        &self,
+        repo_dir: &Path,
+        binaries: &[BinaryTarget],
+    ) -> Result<BuildOutput, anyhow::Error>;
+}
+
+/// Resolves `Auto` by checking whether `nix` is available.
+pub async fn from_kind(kind: BuilderKind, rust_toolchain: &str) -> Box<dyn Builder> {
+    let kind = match kind {
+        BuilderKind::Auto if nix_available().await => BuilderKind::Nix,
+        BuilderKind::Auto => {
+            info!("nix not found, falling back to the cargo builder");
+            BuilderKind::Cargo
+        }
+        kind => kind,
+    };
+    match kind {
+        BuilderKind::Nix => Box::new(NixBuilder),
+        _ => Box::new(CargoBuilder::new(rust_toolchain)),
+    }
+}
+
+async fn nix_available() -> bool {
+    Command::new("nix")
+        .arg("--version")
+        .output()
+        .await
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Builds the flake's default package and verifies it with `nix build
+/// --rebuild`.
+pub struct NixBuilder;
+
+#[async_trait]
+impl Builder for NixBuilder {
+    fn name(&self) -> &'static str {
+        "nix"
+    }
+
+    async fn build(
+        &self,
+        repo_dir: &Path,
+        _binaries: &[BinaryTarget],
+    ) -> Result<BuildOutput, anyhow::Error> {
+        let flake = Flake {
+            flake_path: repo_dir.join("flake.nix"),
+        };
+
+        info!("Building the tool using flake.nix...");
+        let store_path = flake.build(false).await?;
+        info!("Build successful, output at {}", store_path);
+
+        info!("Rebuilding to verify the build is reproducible...");
+        let rebuilt_store_path = flake.build(true).await?;
+        if rebuilt_store_path != store_path {
+            return Err(anyhow::anyhow!(
+                "Rebuild produced a different store path: {} != {}",
+                rebuilt_store_path,
+                store_path
+            ));
+        }
+        let nar_hash = flake.nar_hash(&store_path).await?;
+        info!("Build is reproducible, NAR hash: {}", nar_hash);
+
+        Ok(BuildOutput {
+            bin_dir: PathBuf::from(&store_path).join("bin"),
+            store_path: Some(store_path),
+            nar_hash: Some(nar_hash),
+            reproducible: true,
+            locked_inputs: flake.locked_inputs()?,
+        })
+    }
+}
+
+/// Builds with `cargo build --release --locked` for machines without nix,
+/// pinning the toolchain and stripping environment specifics from the output.
+pub struct CargoBuilder {
+    rust_toolchain: String,
+}
+
+impl CargoBuilder {
+    pub fn new(rust_toolchain: &str) -> Self {
+        CargoBuilder {
+            rust_toolchain: rust_toolchain.to_string(),
+        }
+    }
+
+    /// Writes `rust-toolchain.toml` unless the repository already pins one.
+    fn ensure_toolchain_file(&self, repo_dir: &Path) -> Result<(), anyhow::Error> {
+        if repo_dir.join("rust-toolchain.toml").exists() || repo_dir.join("rust-toolchain").exists()
+        {
+            return Ok(());
+        }
+        info!("Pinning rust toolchain {}", self.rust_toolchain);
+        std::fs::write(
+            repo_dir.join("rust-toolchain.toml"),
+            format!("[toolchain]\nchannel = \"{}\"\n", self.rust_toolchain),
+        )?;
+        Ok(())
+    }
+
+    /// Uses the commit time so embedded timestamps do not depend on when the
+    /// build ran.
+    fn source_date_epoch(repo_dir: &Path) -> Result<i64, anyhow::Error> {
+        Ok(git2::Repository::open(repo_dir)?
+            .head()?
+            .peel_to_commit()?
+            .time()
+            .seconds())
+    }
+}
+
+#[async_trait]
+impl Builder for CargoBuilder {
+    fn name(&self) -> &'static str {
+        "cargo"
+    }
+
+    fn prepare(&self, repo_dir: &Path) -> Result<(), anyhow::Error> {
+        self.ensure_toolchain_file(repo_dir)
+    }
+
+    async fn build(
+        &self,
+        repo_dir: &Path,
+        binaries: &[BinaryTarget],
+    ) -> Result<BuildOutput, anyhow::Error> {
+        let mut rustflags = format!("--remap-path-prefix={}=/build", repo_dir.display());
+        let cargo_home = std::env::var("CARGO_HOME").ok().or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| format!("{}/.cargo", home))
+        });
+        if let Some(cargo_home) = cargo_home {
+            rustflags.push_str(&format!(" --remap-path-prefix={}=/cargo", cargo_home));
+        }
+
+        info!("Building the tool using cargo...");
+        let mut command = Command::new("cargo");
+        command.arg("build").arg("--release").arg("--locked");
+        for binary in binaries {
+            command
+                .arg("--package")
+                .arg(&binary.package)
+                .arg("--bin")
+                .arg(&binary.name);
+        }
+        let output = command
+            .env(
+                "SOURCE_DATE_EPOCH",
+                Self::source_date_epoch(repo_dir)?.to_string(),
+            )
+            .env("RUSTFLAGS", rustflags)
+            .env("CARGO_INCREMENTAL", "0")
+            .current_dir(repo_dir)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let errors = String::from_utf8_lossy(&output.stderr);
+            error!("cargo build failed: {}", errors);
+            return Err(anyhow::anyhow!("cargo build failed: {}", errors));
+        }
+
+        Ok(BuildOutput {
+            bin_dir: cargo::target_directory(repo_dir).await?.join("release"),
+            store_path: None,
+            nar_hash: None,
+            reproducible: false,
+            locked_inputs: Vec::new(),
+        })
+    }
+}
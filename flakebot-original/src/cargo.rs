@@ -0,0 +1,231 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{error, info};
+
+/// A `bin` target of a package in the cloned repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BinaryTarget {
+    pub package: String,
+    pub name: String,
+    /// The binary's entry point, which need not be `src/main.rs`
+    pub src_path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<MetadataPackage>,
+    target_directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct MetadataPackage {
+    name: String,
+    version: String,
+    targets: Vec<MetadataTarget>,
+}
+
+#[derive(Deserialize)]
+struct MetadataTarget {
+    name: String,
+    kind: Vec<String>,
+    src_path: PathBuf,
+}
+
+async fn metadata(repo_dir: &Path) -> Result<Metadata, anyhow::Error> {
+    let output = Command::new("cargo")
+        .arg("metadata")
+        .arg("--format-version")
+        .arg("1")
+        .arg("--no-deps")
+        .current_dir(repo_dir)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let errors = String::from_utf8_lossy(&output.stderr);
+        error!("cargo metadata failed: {}", errors);
+        return Err(anyhow::anyhow!("cargo metadata failed: {}", errors));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Lists the names of the workspace packages using `cargo metadata`.
+pub async fn package_names(repo_dir: &Path) -> Result<Vec<String>, anyhow::Error> {
+    Ok(metadata(repo_dir)
+        .await?
+        .packages
+        .into_iter()
+        .map(|package| package.name)
+        .collect())
+}
+
+/// Looks up the version of a workspace package using `cargo metadata`.
+pub async fn package_version(repo_dir: &Path, package: &str) -> Result<String, anyhow::Error> {
+    metadata(repo_dir)
+        .await?
+        .packages
+        .into_iter()
+        .find(|candidate| candidate.name == package)
+        .map(|candidate| candidate.version)
+        .ok_or_else(|| anyhow::anyhow!("Package {} not found", package))
+}
+
+/// The directory cargo builds into, which honours `CARGO_TARGET_DIR`, the
+/// `build.target-dir` setting and workspaces rooted above `repo_dir`.
+pub async fn target_directory(repo_dir: &Path) -> Result<PathBuf, anyhow::Error> {
+    Ok(metadata(repo_dir).await?.target_directory)
+}
+
+/// Lists every binary target of the workspace using `cargo metadata`.
+pub async fn binary_targets(repo_dir: &Path) -> Result<Vec<BinaryTarget>, anyhow::Error> {
+    info!("Discovering binary targets...");
+    let metadata = metadata(repo_dir).await?;
+    let targets: Vec<BinaryTarget> = metadata
+        .packages
+        .into_iter()
+        .flat_map(|package| {
+            let package_name = package.name;
+            package
+                .targets
+                .into_iter()
+                .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+                .map(move |target| BinaryTarget {
+                    package: package_name.clone(),
+                    name: target.name,
+                    src_path: target.src_path,
+                })
+        })
+        .collect();
+    info!(
+        "Binary targets: {}",
+        targets
+            .iter()
+            .map(|target| target.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(targets)
+}
+
+/// The entry point of the first library target of the workspace, such as
+/// `src/lib.rs`, using `cargo metadata`.
+pub async fn library_source_path(repo_dir: &Path) -> Result<PathBuf, anyhow::Error> {
+    metadata(repo_dir)
+        .await?
+        .packages
+        .into_iter()
+        .flat_map(|package| package.targets)
+        .find(|target| {
+            target
+                .kind
+                .iter()
+                .any(|kind| kind.ends_with("lib") || kind == "proc-macro")
+        })
+        .map(|target| target.src_path)
+        .ok_or_else(|| anyhow::anyhow!("Repository has no library target"))
+}
+
+/// Picks the binaries to build. Requested names are matched exactly; with no
+/// request every binary target is selected, which is none for library crates.
+pub fn select_binaries(
+    targets: &[BinaryTarget],
+    requested: &[String],
+) -> Result<Vec<BinaryTarget>, anyhow::Error> {
+    if requested.is_empty() {
+        return Ok(targets.to_vec());
+    }
+
+    requested
+        .iter()
+        .map(|name| {
+            targets
+                .iter()
+                .find(|target| &target.name == name)
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Binary {} not found, available: {}",
+                        name,
+                        targets
+                            .iter()
+                            .map(|target| target.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    fn target(package: &str, name: &str) -> BinaryTarget {
+        BinaryTarget {
+            package: package.to_string(),
+            name: name.to_string(),
+            src_path: PathBuf::from(format!("src/bin/{}.rs", name)),
+        }
+    }
+
+    #[test]
+    fn requested_binaries_are_selected_in_order() {
+        let targets = [target("cli", "cli"), target("server", "serve")];
+        assert_eq!(select_binaries(&targets, &[]).unwrap(), targets);
+        assert_eq!(
+            select_binaries(&targets, &["serve".to_string(), "cli".to_string()]).unwrap(),
+            [target("server", "serve"), target("cli", "cli")]
+        );
+    }
+
+    #[test]
+    fn library_crates_select_no_binaries() {
+        assert!(select_binaries(&[], &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_binaries_are_errors() {
+        let targets = [target("cli", "cli"), target("server", "serve")];
+        let error = select_binaries(&targets, &["server".to_string()]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Binary server not found, available: cli, serve"
+        );
+        assert!(select_binaries(&[], &["cli".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn targets_are_found_wherever_their_sources_are() {
+        let dir = test_dir("cargo-targets");
+        std::fs::create_dir_all(dir.join("crates/core")).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            r#"
+            [package]
+            name = "rg"
+            version = "0.1.0"
+            edition = "2021"
+            [lib]
+            path = "crates/core/lib.rs"
+            [[bin]]
+            name = "rg"
+            path = "crates/core/main.rs"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("crates/core/main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.join("crates/core/lib.rs"), "").unwrap();
+
+        let targets = binary_targets(&dir).await.unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].name, "rg");
+        assert!(targets[0].src_path.ends_with("crates/core/main.rs"));
+        let library = library_source_path(&dir).await.unwrap();
+        assert!(library.ends_with("crates/core/lib.rs"));
+    }
+}
@@ -1,27 +1,302 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 
-/// Deterministic Program Crafter is an agent tool for building other agent
-/// tools correctly, deterministically, and reproducibly.
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::app::Stage;
+use crate::builder::BuilderKind;
+use crate::flake::InputOverride;
+use crate::serve::Transport;
+use crate::settings::Settings;
+use crate::smoke::ToolKind;
+
+/// Flakebot builds agent tools correctly, deterministically, and
+/// reproducibly: it finds a crate for the instructions, nixifies it,
+/// implements the feature, then builds, tests and publishes the tool.
 #[derive(Parser)]
-#[clap(version = "1.0", author = "Kody Low")]
+#[clap(name = "flakebot", version, author = "Kody Low")]
 pub struct CliArgs {
-    /// The agent instructions
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the selected stages, by default from finding the crate to the
+    /// feature PR
+    Craft(Box<CraftArgs>),
+    /// Find a crate for the instructions, fork it and open the flake.nix PR
+    Flake(Box<CraftArgs>),
+    /// Build the tool cloned in the work directory and attest the build
+    Build(Box<CraftArgs>),
+    /// Smoke test the built tool
+    Verify(Box<CraftArgs>),
+    /// Push the feature, open its PR and register the tool
+    Pr(Box<CraftArgs>),
+    /// Continue the run in the work directory from its last completed stage
+    Resume(Box<CraftArgs>),
+    /// Work with tool manifests
+    #[clap(subcommand)]
+    Manifest(ManifestCommand),
+    /// Inspect and maintain the local registry of built tools
+    Registry(RegistryArgs),
+    /// Serve registered tools to agents over the Model Context Protocol
+    Serve(ServeArgs),
+    /// Inspect the configuration from flakebot.toml, the environment and flags
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration with secrets redacted
+    Show(Box<CraftArgs>),
+}
+
+#[derive(Subcommand)]
+pub enum ManifestCommand {
+    /// Check that a tool manifest is complete and consistent
+    Validate {
+        /// Path to the manifest
+        #[clap(default_value = "./work_dir/flakebot-tool.json")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Args)]
+pub struct RegistryArgs {
+    /// Registry directory, defaults to $XDG_DATA_HOME/flakebot/registry
+    #[clap(long, env = "FLAKEBOT_REGISTRY")]
+    pub registry_dir: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    pub command: RegistryCommand,
+}
+
+#[derive(Subcommand)]
+pub enum RegistryCommand {
+    /// List every registered tool version
+    List {
+        /// Print the index entries as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print the manifest of a registered tool
+    Show {
+        name: String,
+        /// Version to show, defaults to the most recently registered
+        #[clap(long)]
+        version: Option<String>,
+    },
+    /// Remove a tool from the registry
+    Remove {
+        name: String,
+        /// Version to remove, defaults to every version
+        #[clap(long)]
+        version: Option<String>,
+    },
+    /// Drop broken entries and files no entry refers to
+    Gc {
+        /// Only keep this many of the most recent versions of each tool
+        #[clap(long)]
+        keep: Option<usize>,
+    },
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Registry directory, defaults to $XDG_DATA_HOME/flakebot/registry
+    #[clap(long, env = "FLAKEBOT_REGISTRY")]
+    pub registry_dir: Option<PathBuf>,
+
+    /// How MCP clients connect
+    #[clap(long, arg_enum, default_value = "stdio")]
+    pub transport: Transport,
+
+    /// Address the http transport listens on
+    #[clap(long, default_value = "127.0.0.1:8765")]
+    pub listen: std::net::SocketAddr,
+
+    /// Registered tool to serve, by name (repeatable, defaults to all tools)
+    #[clap(long = "tool")]
+    pub tools: Vec<String>,
+
+    /// Seconds a tool call may take before it is aborted
+    #[clap(long, default_value = "30")]
+    pub call_timeout: u64,
+
+    /// Seconds to wait for an HTTP tool's server to accept connections
+    #[clap(long, default_value = "30")]
+    pub startup_timeout: u64,
+}
+
+#[derive(Args)]
+pub struct CraftArgs {
+    /// The agent instructions, required unless a run in the work directory
+    /// is continued
     #[clap(long)]
-    pub instructions: String,
+    pub instructions: Option<String>,
 
-    /// The directory to clone the repository into
-    #[clap(long, default_value = "./work_dir")]
-    pub work_dir: std::path::PathBuf,
+    /// Config file read instead of ./flakebot.toml, on top of
+    /// ~/.config/flakebot/config.toml
+    #[clap(long, env = "FLAKEBOT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Profile of the config files applied over their top level settings
+    #[clap(long, env = "FLAKEBOT_PROFILE")]
+    pub profile: Option<String>,
+
+    /// The directory to clone the repository into [default: ./work_dir]
+    #[clap(long)]
+    pub work_dir: Option<PathBuf>,
 
     /// The Groq API key
-    #[clap(long, env = "GROQ_API_KEY")]
-    pub groq_api_key: String,
+    #[clap(long, env = "GROQ_API_KEY", hide_env_values = true)]
+    pub groq_api_key: Option<String>,
 
     /// Github token for forking repositories
-    #[clap(long, env = "GITHUB_TOKEN")]
-    pub github_token: String,
+    #[clap(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    pub github_token: Option<String>,
 
     /// Cookie for crates.io session
-    #[clap(long, env = "CARGO_COOKIE")]
-    pub cargo_cookie: String,
+    #[clap(long, env = "CARGO_COOKIE", hide_env_values = true)]
+    pub cargo_cookie: Option<String>,
+
+    /// Override a flake input when locking, as NAME=FLAKE_REF (repeatable)
+    #[clap(long = "override-input")]
+    pub override_inputs: Vec<InputOverride>,
+
+    /// Flake registry used to resolve indirect flake inputs when locking
+    #[clap(long, env = "FLAKEBOT_FLAKE_REGISTRY")]
+    pub flake_registry: Option<PathBuf>,
+
+    /// flake.nix template written to repositories without one, defaults to
+    /// the reference flake built into flakebot
+    #[clap(long, env = "FLAKEBOT_REFERENCE_FLAKE")]
+    pub reference_flake: Option<PathBuf>,
+
+    /// Hex-encoded ed25519 seed used to sign build attestations, generated in
+    /// the work directory if not given
+    #[clap(long, env = "FLAKEBOT_ATTESTATION_KEY")]
+    pub attestation_key: Option<PathBuf>,
+
+    /// Binary target to build, by name (repeatable, defaults to all binaries)
+    #[clap(long = "binary")]
+    pub binaries: Vec<String>,
+
+    /// How to build the binaries: nix, cargo, or auto to use nix when available
+    /// [default: auto]
+    #[clap(long, arg_enum)]
+    pub builder: Option<BuilderKind>,
+
+    /// Rust toolchain pinned by the cargo builder when the repository has none
+    /// [default: 1.78.0]
+    #[clap(long)]
+    pub rust_toolchain: Option<String>,
+
+    /// Seconds to wait for the built server to accept connections [default: 30]
+    #[clap(long)]
+    pub smoke_startup_timeout: Option<u64>,
+
+    /// Seconds a built daemon must keep running to pass [default: 10]
+    #[clap(long)]
+    pub daemon_uptime: Option<u64>,
+
+    /// Kind of tool being built, detected from the code when not given
+    #[clap(long, arg_enum)]
+    pub tool_kind: Option<ToolKind>,
+
+    /// Registry the built tool is added to, defaults to
+    /// $XDG_DATA_HOME/flakebot/registry
+    #[clap(long, env = "FLAKEBOT_REGISTRY")]
+    pub registry_dir: Option<PathBuf>,
+
+    /// Stages to run, comma separated, defaults to every stage
+    #[clap(long, arg_enum, use_value_delimiter = true)]
+    pub stages: Vec<Stage>,
+
+    /// Stages not to run, comma separated
+    #[clap(long, arg_enum, use_value_delimiter = true)]
+    pub skip: Vec<Stage>,
+
+    /// Work in this git checkout instead of finding a crate and forking it
+    #[clap(long)]
+    pub repo_path: Option<PathBuf>,
+
+    /// Repository URL to fork, or path of a git checkout, instead of finding
+    /// a crate
+    #[clap(long)]
+    pub repo: Option<String>,
+
+    /// Crate to look up on crates.io instead of asking the LLM for one
+    #[clap(long = "crate")]
+    pub crate_name: Option<String>,
+
+    /// Create a new crate with this name instead of looking one up
+    #[clap(long)]
+    pub scaffold: Option<String>,
+
+    /// Crate, such as axum_template, copied into new crates. With a template,
+    /// a new crate is also created when no crate is found
+    #[clap(long)]
+    pub template: Option<PathBuf>,
+}
+
+impl CraftArgs {
+    /// The configuration files merged with the flags and environment
+    /// variables given, which take precedence.
+    pub fn settings(&self) -> Result<Settings, anyhow::Error> {
+        let mut overrides = toml::Table::new();
+        set(&mut overrides, "work_dir", &self.work_dir)?;
+        set(&mut overrides, "groq_api_key", &self.groq_api_key)?;
+        set(&mut overrides, "github_token", &self.github_token)?;
+        set(&mut overrides, "cargo_cookie", &self.cargo_cookie)?;
+        if !self.override_inputs.is_empty() {
+            set(
+                &mut overrides,
+                "override_inputs",
+                &Some(&self.override_inputs),
+            )?;
+        }
+        set(&mut overrides, "flake_registry", &self.flake_registry)?;
+        set(&mut overrides, "reference_flake", &self.reference_flake)?;
+        set(&mut overrides, "attestation_key", &self.attestation_key)?;
+        if !self.binaries.is_empty() {
+            set(&mut overrides, "binaries", &Some(&self.binaries))?;
+        }
+        set(&mut overrides, "builder", &self.builder)?;
+        set(&mut overrides, "rust_toolchain", &self.rust_toolchain)?;
+        set(
+            &mut overrides,
+            "smoke_startup_timeout",
+            &self.smoke_startup_timeout,
+        )?;
+        set(&mut overrides, "daemon_uptime", &self.daemon_uptime)?;
+        set(&mut overrides, "tool_kind", &self.tool_kind)?;
+        set(&mut overrides, "registry_dir", &self.registry_dir)?;
+        if !self.stages.is_empty() {
+            set(&mut overrides, "stages", &Some(&self.stages))?;
+        }
+        if !self.skip.is_empty() {
+            set(&mut overrides, "skip", &Some(&self.skip))?;
+        }
+        set(&mut overrides, "repo_path", &self.repo_path)?;
+        set(&mut overrides, "repo", &self.repo)?;
+        set(&mut overrides, "crate", &self.crate_name)?;
+        set(&mut overrides, "scaffold", &self.scaffold)?;
+        set(&mut overrides, "template", &self.template)?;
+        Settings::load(self.config.as_deref(), self.profile.as_deref(), overrides)
+    }
+}
+
+fn set<T: Serialize>(
+    overrides: &mut toml::Table,
+    key: &str,
+    value: &Option<T>,
+) -> Result<(), anyhow::Error> {
+    if let Some(value) = value {
+        overrides.insert(key.to_string(), toml::Value::try_from(value)?);
+    }
+    Ok(())
 }
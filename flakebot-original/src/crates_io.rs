@@ -1,6 +1,15 @@
+use async_trait::async_trait;
 use reqwest::Client;
 use tracing::{error, info};
 
+/// Where crates are looked up by name. Implemented by [`CratesIo`].
+#[async_trait]
+pub trait CrateRegistry: Send + Sync {
+    /// Returns the repository URL of the crate, if it is known.
+    async fn search(&self, crate_name: &str) -> Option<String>;
+}
+
+/// [`CrateRegistry`] backed by the crates.io API.
 pub struct CratesIo {
     client: Client,
     cargo_cookie: String,
@@ -13,8 +22,11 @@ impl CratesIo {
             cargo_cookie,
         }
     }
+}
 
-    pub async fn search(&self, crate_name: &str) -> Option<String> {
+#[async_trait]
+impl CrateRegistry for CratesIo {
+    async fn search(&self, crate_name: &str) -> Option<String> {
         info!("Searching crates.io for {}", crate_name);
         let request_url = format!("https://crates.io/api/v1/crates/{}", crate_name);
         let cookie = format!("cargo_session={}", self.cargo_cookie);
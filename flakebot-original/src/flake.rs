@@ -1,39 +1,149 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 use tracing::{error, info};
+
+use crate::cargo::BinaryTarget;
+
+/// An input override applied when locking the flake, given as
+/// `NAME=FLAKE_REF` (e.g. `nixpkgs=github:nixos/nixpkgs/<rev>`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct InputOverride {
+    pub name: String,
+    pub flake_ref: String,
+}
+
+impl FromStr for InputOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, flake_ref)) if !name.is_empty() && !flake_ref.is_empty() => {
+                Ok(InputOverride {
+                    name: name.to_string(),
+                    flake_ref: flake_ref.to_string(),
+                })
+            }
+            _ => Err(format!("Expected NAME=FLAKE_REF, got: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for InputOverride {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<InputOverride> for String {
+    fn from(input_override: InputOverride) -> String {
+        format!("{}={}", input_override.name, input_override.flake_ref)
+    }
+}
+
+/// A direct input of the flake as pinned in `flake.lock`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedInput {
+    pub name: String,
+    pub source: String,
+    pub rev: Option<String>,
+    pub nar_hash: String,
+}
+
+#[derive(Deserialize)]
+struct FlakeLock {
+    nodes: BTreeMap<String, FlakeLockNode>,
+    root: String,
+}
+
+#[derive(Deserialize)]
+struct FlakeLockNode {
+    #[serde(default)]
+    inputs: BTreeMap<String, serde_json::Value>,
+    locked: Option<FlakeLockRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FlakeLockRef {
+    #[serde(rename = "type")]
+    kind: String,
+    owner: Option<String>,
+    repo: Option<String>,
+    url: Option<String>,
+    path: Option<String>,
+    rev: Option<String>,
+    nar_hash: String,
+}
+
+impl FlakeLockRef {
+    fn source(&self) -> String {
+        match (
+            self.kind.as_str(),
+            &self.owner,
+            &self.repo,
+            &self.url,
+            &self.path,
+        ) {
+            (kind, Some(owner), Some(repo), _, _) => format!("{}:{}/{}", kind, owner, repo),
+            (_, _, _, Some(url), _) => url.clone(),
+            (kind, _, _, _, Some(path)) => format!("{}:{}", kind, path),
+            (kind, ..) => kind.to_string(),
+        }
+    }
+}
+
+/// The flake.nix template written to repositories without one.
+pub const REFERENCE_FLAKE: &str = include_str!("../../reference_flake.nix");
+
 pub struct Flake {
     pub flake_path: PathBuf,
 }
 
 impl Flake {
-    pub fn new(crate_name: &str, work_dir: &PathBuf) -> Self {
-        let flake_path = work_dir.join(crate_name).join("flake.nix");
+    /// The flake at the root of the repository checked out in `repo_dir`.
+    pub fn new(repo_dir: &Path) -> Self {
+        let flake_path = repo_dir.join("flake.nix");
         Flake { flake_path }
     }
 
+    fn flake_dir(&self) -> Result<&Path, anyhow::Error> {
+        self.flake_path.parent().ok_or_else(|| {
+            anyhow::anyhow!(
+                "flake.nix has no parent directory: {}",
+                self.flake_path.display()
+            )
+        })
+    }
+
+    /// Writes the reference flake, [`REFERENCE_FLAKE`] unless
+    /// `reference_flake_path` is given, if the repository has no flake.nix.
     pub async fn ensure_flake_nix(
         &self,
-        reference_flake_path: &PathBuf,
+        reference_flake_path: Option<&Path>,
     ) -> Result<(), anyhow::Error> {
-        info!("Reference flake path: {}", reference_flake_path.display());
-
-        if !reference_flake_path.exists() {
-            info!(
-                "Reference flake.nix not found at {}",
-                reference_flake_path.display()
-            );
-            return Ok(());
-        }
-
         if self.flake_path.exists() {
             info!("Found a flake.nix at {}", self.flake_path.display());
         } else {
             info!("Creating flake.nix at {}", self.flake_path.display());
-            let contents = std::fs::read_to_string(reference_flake_path).map_err(|e| {
-                info!("Failed to read reference flake.nix: {}", e);
-                e
-            })?;
+            let contents = match reference_flake_path {
+                Some(reference_flake_path) => std::fs::read_to_string(reference_flake_path)
+                    .map_err(|e| {
+                        anyhow::anyhow!(
+                            "Failed to read reference flake.nix {}: {}",
+                            reference_flake_path.display(),
+                            e
+                        )
+                    })?,
+                None => REFERENCE_FLAKE.to_string(),
+            };
             std::fs::write(&self.flake_path, contents).map_err(|e| {
                 info!(
                     "Failed to write to flake.nix at {}: {}",
@@ -46,16 +156,32 @@ impl Flake {
         Ok(())
     }
 
-    pub async fn write_description_and_binary_name(
+    /// Fills in the template placeholders. The first binary becomes the main
+    /// program and the packages of all binaries are built.
+    pub async fn write_description_and_binaries(
         &self,
         crate_description: &str,
-        binary_name: &str,
+        binaries: &[BinaryTarget],
     ) -> Result<(), anyhow::Error> {
+        let main_binary = binaries
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No binaries selected for flake.nix"))?;
+        let mut packages: Vec<&str> = binaries.iter().map(|b| b.package.as_str()).collect();
+        packages.sort();
+        packages.dedup();
+        let packages = packages
+            .iter()
+            .map(|package| format!("\"{}\"", package))
+            .collect::<Vec<_>>()
+            .join(" ");
+
         let updated_flake_contents = std::fs::read_to_string(&self.flake_path)
             .map_err(|e| anyhow::anyhow!("Failed to read flake.nix: {}", e))?;
         let updated_flake_contents = updated_flake_contents
             .replace("REPLACE-ME-WITH-CRATE-DESCRIPTION", crate_description)
-            .replace("REPLACE-ME-WITH-CRATE-BINARY-NAME", binary_name);
+            .replace("REPLACE-ME-WITH-CRATE-PACKAGE-NAME", &main_binary.package)
+            .replace("REPLACE-ME-WITH-CRATE-BINARY-NAME", &main_binary.name)
+            .replace("\"REPLACE-ME-WITH-CRATE-PACKAGES\"", &packages);
         std::fs::write(&self.flake_path, updated_flake_contents).map_err(|e| {
             anyhow::anyhow!(
                 "Failed to write to flake.nix at {}: {}",
@@ -73,6 +199,7 @@ impl Flake {
             .arg("check")
             .arg("-L")
             .arg(".")
+            .current_dir(self.flake_dir()?)
             .output()
             .await?;
 
@@ -85,6 +212,137 @@ impl Flake {
         Ok(())
     }
 
+    /// Builds the default package and returns its store path. With `rebuild`
+    /// the existing output is rebuilt and compared bit-for-bit, failing if the
+    /// build is not reproducible.
+    pub async fn build(&self, rebuild: bool) -> Result<String, anyhow::Error> {
+        let mut command = Command::new("nix");
+        command.arg("build").arg("--print-out-paths");
+        if rebuild {
+            command.arg("--rebuild");
+        }
+        let output = command.current_dir(self.flake_dir()?).output().await?;
+
+        if !output.status.success() {
+            let errors = String::from_utf8_lossy(&output.stderr);
+            error!("nix build failed: {}", errors);
+            return Err(anyhow::anyhow!("nix build failed: {}", errors));
+        }
+
+        let stdout = String::from_utf8(output.stdout)?;
+        stdout
+            .lines()
+            .next()
+            .map(|line| line.trim().to_string())
+            .ok_or_else(|| anyhow::anyhow!("nix build did not print an output path"))
+    }
+
+    /// Returns the NAR hash of a store path as reported by `nix path-info`.
+    pub async fn nar_hash(&self, store_path: &str) -> Result<String, anyhow::Error> {
+        let output = Command::new("nix")
+            .arg("path-info")
+            .arg("--json")
+            .arg(store_path)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let errors = String::from_utf8_lossy(&output.stderr);
+            error!("nix path-info failed: {}", errors);
+            return Err(anyhow::anyhow!("nix path-info failed: {}", errors));
+        }
+
+        // Older nix versions print a list of entries, newer ones an object keyed
+        // by store path
+        let path_info: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let entry = match &path_info {
+            serde_json::Value::Array(entries) => entries.first(),
+            serde_json::Value::Object(entries) => entries.get(store_path),
+            _ => None,
+        };
+        entry
+            .and_then(|entry| entry["narHash"].as_str())
+            .map(|nar_hash| nar_hash.to_string())
+            .ok_or_else(|| anyhow::anyhow!("nix path-info returned no narHash for {}", store_path))
+    }
+
+    /// Runs `nix flake lock` next to `flake.nix`, applying the given input
+    /// overrides and optional flake registry, and returns the pinned inputs.
+    pub async fn lock(
+        &self,
+        overrides: &[InputOverride],
+        flake_registry: Option<&Path>,
+    ) -> Result<Vec<LockedInput>, anyhow::Error> {
+        let flake_dir = self.flake_dir()?;
+
+        // Nix only sees files tracked by git when the flake lives in a repository
+        let status = Command::new("git")
+            .arg("add")
+            .arg("flake.nix")
+            .current_dir(flake_dir)
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to stage flake.nix"));
+        }
+
+        info!("Locking flake inputs...");
+        let mut command = Command::new("nix");
+        command.arg("flake").arg("lock");
+        for input_override in overrides {
+            info!(
+                "Overriding input {} with {}",
+                input_override.name, input_override.flake_ref
+            );
+            command
+                .arg("--override-input")
+                .arg(&input_override.name)
+                .arg(&input_override.flake_ref);
+        }
+        if let Some(flake_registry) = flake_registry {
+            command.arg("--flake-registry").arg(flake_registry);
+        }
+        let output = command.current_dir(flake_dir).output().await?;
+
+        if !output.status.success() {
+            let errors = String::from_utf8_lossy(&output.stderr);
+            error!("nix flake lock failed: {}", errors);
+            return Err(anyhow::anyhow!("nix flake lock failed: {}", errors));
+        }
+
+        self.locked_inputs()
+    }
+
+    /// Reads the direct inputs pinned in the `flake.lock` next to `flake.nix`.
+    pub fn locked_inputs(&self) -> Result<Vec<LockedInput>, anyhow::Error> {
+        let lock_path = self.flake_dir()?.join("flake.lock");
+        let contents = std::fs::read_to_string(&lock_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", lock_path.display(), e))?;
+        let lock: FlakeLock = serde_json::from_str(&contents)?;
+        let root = lock
+            .nodes
+            .get(&lock.root)
+            .ok_or_else(|| anyhow::anyhow!("flake.lock has no root node"))?;
+
+        let mut locked_inputs = Vec::new();
+        for (name, target) in &root.inputs {
+            // Inputs that follow another input are given as a path, skip those
+            let Some(node_name) = target.as_str() else {
+                continue;
+            };
+            let Some(locked) = lock.nodes.get(node_name).and_then(|n| n.locked.as_ref()) else {
+                continue;
+            };
+            locked_inputs.push(LockedInput {
+                name: name.clone(),
+                source: locked.source(),
+                rev: locked.rev.clone(),
+                nar_hash: locked.nar_hash.clone(),
+            });
+        }
+        Ok(locked_inputs)
+    }
+
     // pub async fn install_flakebox(&self, repo_dir: &PathBuf) -> Result<(),
     // anyhow::Error> {     info!("Installing flakebox files...");
     //     let output = Command::new("flakebox")
@@ -125,3 +383,135 @@ impl Flake {
     //     Ok(())
     // }
 }
+
+/// Renders the pinned inputs as a markdown section for a pull request body.
+pub fn pinned_inputs_summary(locked_inputs: &[LockedInput]) -> String {
+    let mut summary = String::from(
+        "### Pinned flake inputs\n\n| Input | Source | Revision | NAR hash |\n|---|---|---|---|\n",
+    );
+    for input in locked_inputs {
+        let _ = writeln!(
+            summary,
+            "| {} | {} | `{}` | `{}` |",
+            input.name,
+            input.source,
+            input.rev.as_deref().unwrap_or("-"),
+            input.nar_hash
+        );
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn input_overrides_are_parsed() {
+        let input_override: InputOverride = "nixpkgs=github:nixos/nixpkgs/abc=def".parse().unwrap();
+        assert_eq!(input_override.name, "nixpkgs");
+        assert_eq!(input_override.flake_ref, "github:nixos/nixpkgs/abc=def");
+        assert_eq!(
+            String::from(input_override),
+            "nixpkgs=github:nixos/nixpkgs/abc=def"
+        );
+
+        for invalid in ["nixpkgs", "=github:nixos/nixpkgs", "nixpkgs=", ""] {
+            assert!(invalid.parse::<InputOverride>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn locked_inputs_are_the_direct_inputs_of_the_root() {
+        let dir = test_dir("locked-inputs");
+        let lock = serde_json::json!({
+            "nodes": {
+                "nixpkgs": {
+                    "locked": {
+                        "type": "github",
+                        "owner": "nixos",
+                        "repo": "nixpkgs",
+                        "rev": "abc123",
+                        "narHash": "sha256-nixpkgs"
+                    }
+                },
+                "local": {
+                    "locked": {
+                        "type": "path",
+                        "path": "/src/local",
+                        "narHash": "sha256-local"
+                    }
+                },
+                "root": {
+                    "inputs": {
+                        "nixpkgs": "nixpkgs",
+                        "local": "local",
+                        "follows": ["nixpkgs"]
+                    }
+                }
+            },
+            "root": "root",
+            "version": 7
+        });
+        std::fs::write(dir.join("flake.lock"), lock.to_string()).unwrap();
+
+        let locked_inputs = Flake::new(&dir).locked_inputs().unwrap();
+        let summary: Vec<_> = locked_inputs
+            .iter()
+            .map(|input| {
+                (
+                    input.name.as_str(),
+                    input.source.as_str(),
+                    input.rev.as_deref(),
+                    input.nar_hash.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("local", "path:/src/local", None, "sha256-local"),
+                (
+                    "nixpkgs",
+                    "github:nixos/nixpkgs",
+                    Some("abc123"),
+                    "sha256-nixpkgs"
+                ),
+            ]
+        );
+        assert!(pinned_inputs_summary(&locked_inputs)
+            .contains("| nixpkgs | github:nixos/nixpkgs | `abc123` |"));
+    }
+
+    #[test]
+    fn missing_lock_files_are_errors() {
+        let dir = test_dir("missing-lock");
+        assert!(Flake::new(&dir).locked_inputs().is_err());
+    }
+
+    #[tokio::test]
+    async fn reference_flakes_are_only_written_when_missing() {
+        let dir = test_dir("reference-flake");
+        let flake = Flake::new(&dir);
+        flake.ensure_flake_nix(None).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&flake.flake_path).unwrap(),
+            REFERENCE_FLAKE
+        );
+
+        std::fs::write(&flake.flake_path, "{ }").unwrap();
+        flake.ensure_flake_nix(None).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&flake.flake_path).unwrap(), "{ }");
+
+        std::fs::remove_file(&flake.flake_path).unwrap();
+        let custom = dir.join("custom.nix");
+        assert!(flake.ensure_flake_nix(Some(&custom)).await.is_err());
+        std::fs::write(&custom, "{ custom }").unwrap();
+        flake.ensure_flake_nix(Some(&custom)).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&flake.flake_path).unwrap(),
+            "{ custom }"
+        );
+    }
+}
@@ -1,14 +1,38 @@
 use std::fs::remove_dir_all;
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
 
 use anyhow::Error;
+use async_trait::async_trait;
 use reqwest::Client;
 use serde_json::Value;
 use tracing::{error, info};
 
-use crate::groq::Groq;
+use crate::groq::Llm;
 
+/// Where crates are forked to and pull requests are opened. Implemented by
+/// [`Github`].
+#[async_trait]
+pub trait CodeHost: Send + Sync {
+    /// Forks the repository and clones the fork into `work_dir`.
+    async fn fork_and_clone(&mut self, repo_url: &str, work_dir: &Path) -> Result<(), Error>;
+
+    async fn create_branch(&self, repo_dir: &Path, branch_name: &str) -> Result<(), Error>;
+
+    async fn push_changes(&self, repo_dir: &Path) -> Result<(), Error>;
+
+    /// Opens a pull request for the current branch, described by the LLM from
+    /// the last commit's diff with `extra_body` appended.
+    async fn open_pull_request(
+        &self,
+        repo_dir: &Path,
+        llm: &dyn Llm,
+        extra_body: Option<&str>,
+    ) -> Result<(), Error>;
+}
+
+/// [`CodeHost`] using the GitHub API for forks, git for branches and pushes
+/// and the `gh` CLI for pull requests.
 pub struct Github {
     client: Client,
     github_token: String,
@@ -24,18 +48,8 @@ impl Github {
         }
     }
 
-    pub async fn fork_and_clone(
-        &mut self,
-        crate_tool: &str,
-        work_dir: &PathBuf,
-    ) -> Result<(), anyhow::Error> {
-        let forked_repo_url = self.fork_repo(crate_tool).await?;
-        self.repo_url = forked_repo_url.clone();
-        self.clone_repo(&forked_repo_url, work_dir).await
-    }
-
     pub async fn fork_repo(&self, repo_url: &str) -> Result<String, anyhow::Error> {
-        let repo_name = repo_url.split('/').last().ok_or_else(|| {
+        let repo_name = repo_url.split('/').next_back().ok_or_else(|| {
             anyhow::anyhow!("Repository URL does not contain a name: {}", repo_url)
         })?;
         let repo_owner = repo_url.split('/').nth(3).ok_or_else(|| {
@@ -47,10 +61,10 @@ impl Github {
             return Ok(repo_url.to_string());
         }
 
-        let user_repos_url = format!("https://api.github.com/user/repos");
+        let user_repos_url = "https://api.github.com/user/repos";
         let repos_response = self
             .client
-            .get(&user_repos_url)
+            .get(user_repos_url)
             .bearer_auth(&self.github_token)
             .send()
             .await?;
@@ -83,17 +97,13 @@ impl Github {
             let forked_repo_url = forked_repo["clone_url"]
                 .as_str()
                 .ok_or_else(|| anyhow::anyhow!("Failed to get clone URL from forked repository"))?;
-            return Ok(forked_repo_url.to_string());
+            Ok(forked_repo_url.to_string())
         } else {
-            return Err(anyhow::anyhow!("Failed to fork repository"));
+            Err(anyhow::anyhow!("Failed to fork repository"))
         }
     }
 
-    pub async fn clone_repo(
-        &self,
-        repo_url: &str,
-        work_dir: &PathBuf,
-    ) -> Result<(), anyhow::Error> {
+    pub async fn clone_repo(&self, repo_url: &str, work_dir: &Path) -> Result<(), anyhow::Error> {
         let repo_name = repo_url.split("/").last().ok_or_else(|| {
             anyhow::anyhow!("Repository URL does not contain a name: {}", repo_url)
         })?;
@@ -112,8 +122,21 @@ impl Github {
             Err(e) => Err(anyhow::anyhow!("Failed to clone repository: {}", e)),
         }
     }
+}
+
+#[async_trait]
+impl CodeHost for Github {
+    async fn fork_and_clone(
+        &mut self,
+        crate_tool: &str,
+        work_dir: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let forked_repo_url = self.fork_repo(crate_tool).await?;
+        self.repo_url = forked_repo_url.clone();
+        self.clone_repo(&forked_repo_url, work_dir).await
+    }
 
-    pub async fn create_branch(&self, repo_dir: &PathBuf, branch_name: &str) -> Result<(), Error> {
+    async fn create_branch(&self, repo_dir: &Path, branch_name: &str) -> Result<(), Error> {
         info!("Creating branch: {}", branch_name);
         let status = Command::new("git")
             .args(["checkout", "-b", branch_name])
@@ -125,7 +148,7 @@ impl Github {
         Ok(())
     }
 
-    pub async fn push_changes(&self, repo_dir: &PathBuf) -> Result<(), Error> {
+    async fn push_changes(&self, repo_dir: &Path) -> Result<(), Error> {
         info!("Pushing changes to remote repository...");
         let status = Command::new("git")
             .args(["push", "-f"])
@@ -137,7 +160,12 @@ impl Github {
         Ok(())
     }
 
-    pub async fn open_pull_request(&self, repo_dir: &PathBuf, groq: &Groq) -> Result<(), Error> {
+    async fn open_pull_request(
+        &self,
+        repo_dir: &Path,
+        llm: &dyn Llm,
+        extra_body: Option<&str>,
+    ) -> Result<(), Error> {
         info!("Opening pull request...");
 
         let current_branch_output = Command::new("git")
@@ -169,16 +197,17 @@ impl Github {
         let git_diff_str = String::from_utf8(git_diff)?;
 
         // Generate PR message and title
-        let (pr_title, pr_message) = groq
-            .generate_pr_message_and_title(&self.github_token, &git_diff_str)
-            .await?;
+        let (pr_title, mut pr_message) = llm.generate_pr_message_and_title(&git_diff_str).await?;
+        if let Some(extra_body) = extra_body {
+            pr_message = format!("{}\n\n{}", pr_message, extra_body);
+        }
 
         let status = Command::new("gh")
             .args([
                 "pr",
                 "create",
                 "--head",
-                &format!("{}", current_branch),
+                &current_branch,
                 "--title",
                 &pr_title,
                 "--body",
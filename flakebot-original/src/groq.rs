@@ -1,20 +1,105 @@
-use std::path::PathBuf;
+use std::path::Path;
 
+use async_trait::async_trait;
 use reqwest::{Client, Error};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 use tracing::{error, info};
 
+use crate::smoke::{CliTestPlan, SmokeTestPlan, ToolKind};
+
 use crate::templates::{
-    GROQ_ADD_DEPENDENCY_TEMPLATE, GROQ_COMMIT_MESSAGE_TEMPLATE, GROQ_CRATES_TEMPLATE,
-    GROQ_CRATE_DESCRIPTION_TEMPLATE, GROQ_INTERACTION_INSTRUCTIONS_TEMPLATE,
+    GROQ_ADD_DEPENDENCY_TEMPLATE, GROQ_CLASSIFY_TOOL_TEMPLATE, GROQ_CLI_ARGS_SCHEMA_TEMPLATE,
+    GROQ_CLI_TESTS_TEMPLATE, GROQ_COMMIT_MESSAGE_TEMPLATE, GROQ_CRATES_TEMPLATE,
+    GROQ_CRATE_DESCRIPTION_TEMPLATE, GROQ_INTERACTION_INSTRUCTIONS_TEMPLATE, GROQ_OPENAPI_TEMPLATE,
     GROQ_PR_MESSAGE_TEMPLATE, GROQ_PR_TITLE_TEMPLATE, GROQ_REWRITE_MAIN_RS_TEMPLATE,
-    GROQ_VALIDATE_BINARY_TEMPLATE,
+    GROQ_SMOKE_TESTS_TEMPLATE, GROQ_VALIDATE_BINARY_TEMPLATE,
 };
 
 pub const GROQ_API_BASE_URL: &str = "https://api.groq.com/openai/v1";
 pub const GROQ_BASE_MODEL: &str = "llama3-70b-8192";
 
+/// The language model prompts the pipeline relies on. Implemented by [`Groq`];
+/// embedders can plug in another model or a scripted one for tests.
+#[async_trait]
+pub trait Llm: Send + Sync {
+    /// Names crates that could implement the instructions, best first.
+    async fn get_crates_list(&self, user_instructions: &str) -> Result<Vec<String>, anyhow::Error>;
+
+    /// Describes the crate for the flake's `description`.
+    async fn create_crate_description(
+        &self,
+        cargo_toml_contents: &str,
+        readme_contents: &str,
+        main_rs_contents: &str,
+    ) -> Result<String, anyhow::Error>;
+
+    /// Answers `Correct` when the program satisfies the instructions,
+    /// otherwise the changes to make.
+    async fn validate_binary(
+        &self,
+        instructions: &str,
+        main_rs_contents: &str,
+    ) -> Result<String, anyhow::Error>;
+
+    /// Rewrites `main.rs` following the instructions.
+    async fn rewrite_main_rs(
+        &self,
+        instructions: &str,
+        main_rs_contents: &str,
+    ) -> Result<String, anyhow::Error>;
+
+    /// Adds the dependencies `main.rs` needs to the crate in `repo_dir`.
+    async fn add_cargo_deps(
+        &self,
+        main_rs_contents: &str,
+        repo_dir: &Path,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Explains how to interact with the tool in the style of its kind.
+    async fn get_interaction_instructions(
+        &self,
+        source_path: &Path,
+        kind: ToolKind,
+    ) -> Result<String, anyhow::Error>;
+
+    async fn classify_tool(&self, main_rs_path: &Path) -> Result<ToolKind, anyhow::Error>;
+
+    async fn get_smoke_test_plan(
+        &self,
+        main_rs_path: &Path,
+    ) -> Result<SmokeTestPlan, anyhow::Error>;
+
+    async fn get_cli_test_plan(
+        &self,
+        main_rs_path: &Path,
+        binary_name: &str,
+    ) -> Result<CliTestPlan, anyhow::Error>;
+
+    /// Derives an OpenAPI document from the router in `main.rs`.
+    async fn generate_openapi_spec(
+        &self,
+        main_rs_path: &Path,
+    ) -> Result<serde_json::Value, anyhow::Error>;
+
+    /// Derives a JSON schema of the binary's command line arguments.
+    async fn generate_cli_args_schema(
+        &self,
+        main_rs_path: &Path,
+        binary_name: &str,
+    ) -> Result<serde_json::Value, anyhow::Error>;
+
+    async fn generate_commit_message(&self, git_diff: &str) -> Result<String, anyhow::Error>;
+
+    /// Returns the title and body of a pull request for the diff.
+    async fn generate_pr_message_and_title(
+        &self,
+        git_diff: &str,
+    ) -> Result<(String, String), anyhow::Error>;
+}
+
+/// [`Llm`] backed by the Groq chat completions API.
 pub struct Groq {
     api_key: String,
     base_url: String,
@@ -33,6 +118,18 @@ impl Groq {
         }
     }
 
+    /// Model used for every prompt, [`GROQ_BASE_MODEL`] by default.
+    pub fn model(mut self, model: &str) -> Groq {
+        self.model = model.to_string();
+        self
+    }
+
+    /// Base URL of the chat completions API, [`GROQ_API_BASE_URL`] by default.
+    pub fn base_url(mut self, base_url: &str) -> Groq {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
     pub async fn request_chat_completion(
         &self,
         message: &str,
@@ -73,10 +170,24 @@ impl Groq {
         response.json::<ChatCompletionResponse>().await
     }
 
-    pub async fn get_crates_list(
-        &self,
-        user_instructions: &str,
-    ) -> Result<Vec<String>, anyhow::Error> {
+    /// Requests a completion and parses it as JSON, ignoring code fences.
+    async fn request_json<T: DeserializeOwned>(&self, message: &str) -> Result<T, anyhow::Error> {
+        let response = self.request_chat_completion(message).await?;
+        let content = response
+            .choices
+            .first()
+            .map(|c| strip_code_fences(&c.message.content))
+            .ok_or_else(|| anyhow::anyhow!("No choices in response"))?;
+        serde_json::from_str(&content).map_err(|e| {
+            error!("Response is not valid JSON: {}", content);
+            anyhow::anyhow!("Failed to parse response as JSON: {}", e)
+        })
+    }
+}
+
+#[async_trait]
+impl Llm for Groq {
+    async fn get_crates_list(&self, user_instructions: &str) -> Result<Vec<String>, anyhow::Error> {
         let message = GROQ_CRATES_TEMPLATE.replace("{user_instructions}", user_instructions);
         let response = self.request_chat_completion(&message).await?;
 
@@ -100,7 +211,7 @@ impl Groq {
         }
     }
 
-    pub async fn create_crate_description(
+    async fn create_crate_description(
         &self,
         cargo_toml_contents: &str,
         readme_contents: &str,
@@ -121,7 +232,7 @@ impl Groq {
         }
     }
 
-    pub async fn validate_binary(
+    async fn validate_binary(
         &self,
         instructions: &str,
         main_rs_contents: &str,
@@ -143,7 +254,7 @@ impl Groq {
         }
     }
 
-    pub async fn rewrite_main_rs(
+    async fn rewrite_main_rs(
         &self,
         instructions: &str,
         main_rs_contents: &str,
@@ -160,10 +271,10 @@ impl Groq {
         Ok(response)
     }
 
-    pub async fn add_cargo_deps(
+    async fn add_cargo_deps(
         &self,
         main_rs_contents: &str,
-        repo_dir: &PathBuf,
+        repo_dir: &Path,
     ) -> Result<(), anyhow::Error> {
         let message = GROQ_ADD_DEPENDENCY_TEMPLATE.replace("{main_rs_contents}", main_rs_contents);
         let response = self.request_chat_completion(&message).await?;
@@ -191,13 +302,28 @@ impl Groq {
         Ok(())
     }
 
-    pub async fn get_interaction_instructions(
+    async fn get_interaction_instructions(
         &self,
-        main_rs_path: &PathBuf,
+        source_path: &Path,
+        kind: ToolKind,
     ) -> Result<String, anyhow::Error> {
-        let main_rs_contents = std::fs::read_to_string(main_rs_path)?;
-        let message =
-            GROQ_INTERACTION_INSTRUCTIONS_TEMPLATE.replace("{main_rs_contents}", &main_rs_contents);
+        let source_contents = std::fs::read_to_string(source_path)?;
+        let source_file = source_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let interaction_style = match kind {
+            ToolKind::HttpServer => {
+                "a list of curl commands that the user can use to interact with the program."
+            }
+            ToolKind::Cli => "a list of example command lines showing the arguments and stdin the program accepts.",
+            ToolKind::Library => "example Rust code calling the public API of the library.",
+            ToolKind::Daemon => "instructions for starting, configuring and stopping the program.",
+        };
+        let message = GROQ_INTERACTION_INSTRUCTIONS_TEMPLATE
+            .replace("{interaction_style}", interaction_style)
+            .replace("{source_file}", &source_file)
+            .replace("{main_rs_contents}", &source_contents);
         let response = self.request_chat_completion(&message).await?;
         if let Some(choice) = response.choices.first() {
             if choice.message.content.trim() == "true" {
@@ -210,7 +336,62 @@ impl Groq {
         }
     }
 
-    pub async fn generate_commit_message(&self, git_diff: &str) -> Result<String, anyhow::Error> {
+    async fn classify_tool(&self, main_rs_path: &Path) -> Result<ToolKind, anyhow::Error> {
+        let main_rs_contents = std::fs::read_to_string(main_rs_path)?;
+        let message = GROQ_CLASSIFY_TOOL_TEMPLATE.replace("{main_rs_contents}", &main_rs_contents);
+        let response = self.request_chat_completion(&message).await?;
+        response
+            .choices
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No response from classify request"))?
+            .message
+            .content
+            .parse()
+    }
+
+    async fn get_smoke_test_plan(
+        &self,
+        main_rs_path: &Path,
+    ) -> Result<SmokeTestPlan, anyhow::Error> {
+        let main_rs_contents = std::fs::read_to_string(main_rs_path)?;
+        let message = GROQ_SMOKE_TESTS_TEMPLATE.replace("{main_rs_contents}", &main_rs_contents);
+        self.request_json(&message).await
+    }
+
+    async fn get_cli_test_plan(
+        &self,
+        main_rs_path: &Path,
+        binary_name: &str,
+    ) -> Result<CliTestPlan, anyhow::Error> {
+        let main_rs_contents = std::fs::read_to_string(main_rs_path)?;
+        let message = GROQ_CLI_TESTS_TEMPLATE
+            .replace("{binary_name}", binary_name)
+            .replace("{main_rs_contents}", &main_rs_contents);
+        self.request_json(&message).await
+    }
+
+    async fn generate_openapi_spec(
+        &self,
+        main_rs_path: &Path,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        let main_rs_contents = std::fs::read_to_string(main_rs_path)?;
+        let message = GROQ_OPENAPI_TEMPLATE.replace("{main_rs_contents}", &main_rs_contents);
+        self.request_json(&message).await
+    }
+
+    async fn generate_cli_args_schema(
+        &self,
+        main_rs_path: &Path,
+        binary_name: &str,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        let main_rs_contents = std::fs::read_to_string(main_rs_path)?;
+        let message = GROQ_CLI_ARGS_SCHEMA_TEMPLATE
+            .replace("{binary_name}", binary_name)
+            .replace("{main_rs_contents}", &main_rs_contents);
+        self.request_json(&message).await
+    }
+
+    async fn generate_commit_message(&self, git_diff: &str) -> Result<String, anyhow::Error> {
         let message = GROQ_COMMIT_MESSAGE_TEMPLATE.replace("{git_diff}", git_diff);
         let response = self.request_chat_completion(&message).await?;
 
@@ -222,14 +403,11 @@ impl Groq {
         }
     }
 
-    pub async fn generate_pr_message_and_title(
+    async fn generate_pr_message_and_title(
         &self,
-        github_token: &str,
         git_diff: &str,
     ) -> Result<(String, String), anyhow::Error> {
-        let message = GROQ_PR_MESSAGE_TEMPLATE
-            .replace("{github_token}", github_token)
-            .replace("{git_diff}", git_diff);
+        let message = GROQ_PR_MESSAGE_TEMPLATE.replace("{git_diff}", git_diff);
         let message_response = self.request_chat_completion(&message).await?;
         let message = message_response
             .choices
@@ -247,6 +425,15 @@ impl Groq {
     }
 }
 
+/// Drops Markdown code fence lines the model tends to wrap answers in.
+fn strip_code_fences(content: &str) -> String {
+    content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub messages: Vec<ChatMessage>,
@@ -0,0 +1,103 @@
+//! Flakebot turns natural language instructions into a reproducibly built,
+//! tested and registered agent tool.
+//!
+//! The pipeline is an [`app::App`] made of [`app::Stage`]s: find a crate and
+//! open its flake.nix PR, add flakebox, implement the feature, build and attest
+//! it, smoke test it, then open the feature PR and register the tool. The
+//! services it talks to are trait objects so the pipeline can be embedded
+//! with other implementations:
+//!
+//! - [`groq::Llm`], implemented by [`groq::Groq`]
+//! - [`github::CodeHost`], implemented by [`github::Github`]
+//! - [`crates_io::CrateRegistry`], implemented by [`crates_io::CratesIo`]
+//! - [`builder::Builder`], implemented by [`builder::NixBuilder`] and
+//!   [`builder::CargoBuilder`]
+//!
+//! ```no_run
+//! use flakebot_original::app::{App, Stage};
+//! use flakebot_original::builder::CargoBuilder;
+//! use flakebot_original::crates_io::CratesIo;
+//! use flakebot_original::github::Github;
+//! use flakebot_original::groq::Groq;
+//!
+//! # async fn run() -> Result<(), anyhow::Error> {
+//! let mut app = App::builder("./work_dir")
+//!     .instructions("simple http server with a post endpoint for summing two numbers")
+//!     .llm(Box::new(Groq::new("groq-api-key")))
+//!     .code_host(Box::new(Github::new("github-token".to_string())))
+//!     .crate_registry(Box::new(CratesIo::new("cargo-cookie".to_string())))
+//!     .builder(Box::new(CargoBuilder::new("1.78.0")))
+//!     .build()?;
+//! app.run_stage(Stage::Flake).await?;
+//! app.run_stage(Stage::Build).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use app::{App, Stage};
+use config::{CliArgs, Command, CraftArgs, ManifestCommand};
+
+/// The pipeline and its stages
+pub mod app;
+/// Signed in-toto provenance for built binaries
+pub mod attestation;
+/// Building the selected binaries with nix or cargo
+pub mod builder;
+/// Package and binary target discovery with `cargo metadata`
+pub mod cargo;
+/// Command line arguments
+pub mod config;
+/// Looking crates up on crates.io
+pub mod crates_io;
+/// Writing, locking and building the repository's flake.nix
+pub mod flake;
+/// Forks, branches and pull requests on GitHub
+pub mod github;
+/// The LLM prompts and the Groq client answering them
+pub mod groq;
+/// The `flakebot-tool.json` manifest describing a built tool
+pub mod manifest;
+/// The local registry of built tools
+pub mod registry;
+/// Creating new crates with `cargo new`, optionally from a template
+pub mod scaffold;
+/// Serving registered tools over the Model Context Protocol
+pub mod serve;
+/// Layered `flakebot.toml` configuration and profiles
+pub mod settings;
+/// Smoke test harnesses per tool kind
+pub mod smoke;
+/// Prompt templates
+pub mod templates;
+#[cfg(test)]
+mod test_util;
+
+/// Sets up logging and loads `.env`. Logs go to stderr so stdout only carries
+/// command output and the stdio MCP transport.
+pub fn init_logging_and_env() -> Result<(), anyhow::Error> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+    dotenv::dotenv().ok();
+    Ok(())
+}
+
+/// Runs the command given on the command line.
+pub async fn run(cli_args: CliArgs) -> Result<(), anyhow::Error> {
+    match cli_args.command {
+        Command::Craft(craft_args) => App::new(&craft_args).await?.run().await,
+        Command::Flake(craft_args) => run_stage(&craft_args, Stage::Flake).await,
+        Command::Build(craft_args) => run_stage(&craft_args, Stage::Build).await,
+        Command::Verify(craft_args) => run_stage(&craft_args, Stage::Verify).await,
+        Command::Pr(craft_args) => run_stage(&craft_args, Stage::Pr).await,
+        Command::Resume(craft_args) => App::new(&craft_args).await?.resume().await,
+        Command::Manifest(ManifestCommand::Validate { path }) => manifest::validate_file(&path),
+        Command::Registry(registry_args) => registry::run(registry_args),
+        Command::Serve(serve_args) => serve::run(serve_args).await,
+        Command::Config(config_command) => settings::run(config_command),
+    }
+}
+
+async fn run_stage(craft_args: &CraftArgs, stage: Stage) -> Result<(), anyhow::Error> {
+    App::new(craft_args).await?.run_stage(stage).await
+}
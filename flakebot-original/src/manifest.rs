@@ -0,0 +1,451 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::app;
+use crate::smoke::{SmokeReport, ToolKind};
+
+pub const MANIFEST_FILE_NAME: &str = "flakebot-tool.json";
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// Describes a built tool for agent runtimes: where it comes from, what was
+/// built, how to invoke it and how it was tested.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolManifest {
+    pub schema_version: u32,
+    pub name: String,
+    /// Version of the package the tool was built from
+    pub version: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub flake: FlakeReference,
+    /// Name of the builder that produced the binaries
+    pub builder: String,
+    pub binaries: Vec<ManifestBinary>,
+    pub invocation: Invocation,
+    #[serde(default)]
+    pub environment: Vec<EnvironmentVariable>,
+    pub tests: TestResults,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlakeReference {
+    /// Flake reference locked to `rev`, usable with `nix run`
+    pub url: String,
+    pub rev: String,
+}
+
+impl FlakeReference {
+    /// `repo_url` is a git remote as accepted by [`app::git_url`], or the
+    /// path of a local repository.
+    pub fn locked(repo_url: &str, rev: &str) -> Self {
+        let url = app::git_url(repo_url).unwrap_or_else(|| format!("file://{}", repo_url));
+        FlakeReference {
+            url: format!("git+{}?rev={}", url, rev),
+            rev: rev.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestBinary {
+    pub name: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Invocation {
+    /// Run once per call, arguments described by a JSON schema
+    Cli {
+        binary: String,
+        args_schema: serde_json::Value,
+    },
+    /// Started once and called over HTTP as described by the OpenAPI document
+    Http {
+        binary: String,
+        #[serde(default)]
+        port: Option<u16>,
+        openapi: serde_json::Value,
+    },
+    /// Started once and left running
+    Daemon { binary: String },
+    /// Used as a Rust dependency
+    Library { crate_name: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvironmentVariable {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestResults {
+    pub kind: ToolKind,
+    pub passed: bool,
+    pub total: usize,
+    pub succeeded: usize,
+}
+
+impl From<&SmokeReport> for TestResults {
+    fn from(report: &SmokeReport) -> Self {
+        TestResults {
+            kind: report.kind,
+            passed: report.passed,
+            total: report.results.len(),
+            succeeded: report.results.iter().filter(|r| r.passed).count(),
+        }
+    }
+}
+
+impl ToolManifest {
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        info!("Tool manifest written to {}", path.display());
+        Ok(())
+    }
+
+    /// Returns every problem found in the manifest, empty if it is valid.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.schema_version != MANIFEST_SCHEMA_VERSION {
+            problems.push(format!(
+                "Unsupported schema_version {}, expected {}",
+                self.schema_version, MANIFEST_SCHEMA_VERSION
+            ));
+        }
+        if !is_valid_name(&self.name) {
+            problems.push(format!(
+                "name {:?} must start with a letter or digit and only contain letters, digits, \
+                 '-', '_' and '.'",
+                self.name
+            ));
+        }
+        if let Err(e) = semver::Version::parse(&self.version) {
+            problems.push(format!(
+                "version {:?} is not a semantic version: {}",
+                self.version, e
+            ));
+        }
+        if !is_hex(&self.flake.rev, 40) {
+            problems.push(format!(
+                "flake.rev is not a full git revision: {}",
+                self.flake.rev
+            ));
+        }
+        if !self.flake.url.contains(&self.flake.rev) {
+            problems.push("flake.url is not locked to flake.rev".to_string());
+        }
+        let mut seen = BTreeSet::new();
+        for binary in &self.binaries {
+            if !binary
+                .name
+                .strip_prefix("bin/")
+                .is_some_and(is_path_component)
+            {
+                problems.push(format!(
+                    "Binary {:?} is not a file name under bin/",
+                    binary.name
+                ));
+            }
+            if !seen.insert(binary.name.as_str()) {
+                problems.push(format!("Binary {} is listed more than once", binary.name));
+            }
+            if !is_hex(&binary.sha256, 64) {
+                problems.push(format!(
+                    "Binary {} has an invalid sha256, expected 64 lowercase hex digits",
+                    binary.name
+                ));
+            }
+        }
+
+        let binary_names: BTreeSet<&str> = self
+            .binaries
+            .iter()
+            .map(|binary| binary.name.trim_start_matches("bin/"))
+            .collect();
+        match &self.invocation {
+            Invocation::Cli {
+                binary,
+                args_schema,
+            } => {
+                check_binary(&mut problems, &binary_names, binary);
+                if !args_schema.is_object() {
+                    problems.push("invocation.args_schema is not a JSON schema object".to_string());
+                }
+            }
+            Invocation::Http {
+                binary, openapi, ..
+            } => {
+                check_binary(&mut problems, &binary_names, binary);
+                let version = openapi.get("openapi").and_then(|v| v.as_str());
+                if !version.is_some_and(|version| version.starts_with("3.")) {
+                    problems.push("invocation.openapi is not an OpenAPI 3 document".to_string());
+                }
+            }
+            Invocation::Daemon { binary } => check_binary(&mut problems, &binary_names, binary),
+            Invocation::Library { crate_name } => {
+                if crate_name.trim().is_empty() {
+                    problems.push("invocation.crate_name is empty".to_string());
+                }
+            }
+        }
+
+        if self.tests.succeeded > self.tests.total {
+            problems.push("tests.succeeded is larger than tests.total".to_string());
+        }
+        if self.tests.passed != (self.tests.total > 0 && self.tests.succeeded == self.tests.total) {
+            problems.push("tests.passed does not match the test counts".to_string());
+        }
+        problems
+    }
+}
+
+fn check_binary(problems: &mut Vec<String>, binary_names: &BTreeSet<&str>, binary: &str) {
+    if !binary_names.contains(binary) {
+        problems.push(format!(
+            "invocation.binary {} is not one of the built binaries",
+            binary
+        ));
+    }
+}
+
+/// Lowercase hex as printed by git and sha256sum.
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+/// Tool names become directory names, so they are restricted to a portable
+/// character set.
+pub fn is_valid_name(name: &str) -> bool {
+    name.len() <= 128
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Whether `value` can be joined to a directory without leaving it: a single
+/// plain path component, not `.`, `..`, a root or anything with separators.
+pub fn is_path_component(value: &str) -> bool {
+    !value.contains(['/', '\\'])
+        && matches!(
+            Path::new(value).components().collect::<Vec<_>>()[..],
+            [Component::Normal(component)] if component == value
+        )
+}
+
+/// Finds the environment variables read by the source: `env::var`,
+/// `env::var_os`, `env!`, `option_env!` and clap `env = "..."` attributes.
+pub fn scan_environment_variables(source: &str) -> Vec<EnvironmentVariable> {
+    let patterns = ["var(\"", "var_os(\"", "env!(\"", "env = \""];
+    let mut names = BTreeSet::new();
+    for pattern in patterns {
+        for (index, _) in source.match_indices(pattern) {
+            let rest = &source[index + pattern.len()..];
+            if let Some(end) = rest.find('"') {
+                let name = &rest[..end];
+                if !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+                {
+                    names.insert(name.to_string());
+                }
+            }
+        }
+    }
+    names
+        .into_iter()
+        .map(|name| EnvironmentVariable { name })
+        .collect()
+}
+
+/// Scans every Rust source file under `src_dir` for environment variables.
+pub fn scan_source_dir(src_dir: &Path) -> Result<Vec<EnvironmentVariable>, anyhow::Error> {
+    let mut sources = String::new();
+    let mut dirs = vec![src_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                sources.push_str(&std::fs::read_to_string(&path)?);
+                sources.push('\n');
+            }
+        }
+    }
+    Ok(scan_environment_variables(&sources))
+}
+
+/// Loads and validates a manifest, logging every problem found.
+pub fn validate_file(path: &Path) -> Result<(), anyhow::Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let manifest: ToolManifest = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("{} is not a valid tool manifest: {}", path.display(), e))?;
+
+    let problems = manifest.problems();
+    if problems.is_empty() {
+        info!(
+            "{} is a valid manifest for {}",
+            path.display(),
+            manifest.name
+        );
+        return Ok(());
+    }
+    for problem in &problems {
+        error!("{}", problem);
+    }
+    Err(anyhow::anyhow!(
+        "{} has {} problem(s)",
+        path.display(),
+        problems.len()
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A valid manifest of a CLI tool with one binary.
+    pub(crate) fn manifest(name: &str, version: &str) -> ToolManifest {
+        let rev = "0123456789abcdef0123456789abcdef01234567";
+        ToolManifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            name: name.to_string(),
+            version: version.to_string(),
+            description: None,
+            flake: FlakeReference::locked("https://github.com/owner/tool", rev),
+            builder: "cargo".to_string(),
+            binaries: vec![ManifestBinary {
+                name: "bin/tool".to_string(),
+                sha256: "ab".repeat(32),
+            }],
+            invocation: Invocation::Cli {
+                binary: "tool".to_string(),
+                args_schema: serde_json::json!({ "type": "object" }),
+            },
+            environment: Vec::new(),
+            tests: TestResults {
+                kind: ToolKind::Cli,
+                passed: true,
+                total: 1,
+                succeeded: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn valid_manifests_have_no_problems() {
+        assert_eq!(manifest("tool", "1.2.3").problems(), Vec::<String>::new());
+        assert_eq!(
+            manifest("my_tool.rs-2", "0.1.0-alpha.1+build.5").problems(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn names_must_be_plain_directory_names() {
+        for name in [
+            "",
+            "../..",
+            "/etc",
+            "a/b",
+            "a\\b",
+            ".hidden",
+            "-flag",
+            "tool name",
+        ] {
+            assert_eq!(manifest(name, "1.0.0").problems().len(), 1, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn versions_must_be_semantic_versions() {
+        for version in ["", "1.0", "v1.0.0", "../1.0.0", "/1.0.0"] {
+            let problems = manifest("tool", version).problems();
+            assert_eq!(problems.len(), 1, "{:?}", version);
+            assert!(problems[0].contains("not a semantic version"));
+        }
+    }
+
+    #[test]
+    fn hashes_must_be_lowercase_hex() {
+        let mut invalid = manifest("tool", "1.0.0");
+        invalid.binaries[0].sha256 = "AB".repeat(32);
+        invalid.flake = FlakeReference::locked("https://github.com/owner/tool", "main");
+        let problems = invalid.problems();
+        assert!(problems[0].starts_with("flake.rev is not a full git revision"));
+        assert!(problems[1].contains("invalid sha256"));
+        assert_eq!(problems.len(), 2);
+
+        let mut short = manifest("tool", "1.0.0");
+        short.binaries[0].sha256 = "ab".repeat(31);
+        assert_eq!(short.problems().len(), 1);
+    }
+
+    #[test]
+    fn binaries_must_be_unique_files_under_bin() {
+        let mut duplicated = manifest("tool", "1.0.0");
+        duplicated.binaries.push(duplicated.binaries[0].clone());
+        assert_eq!(
+            duplicated.problems(),
+            ["Binary bin/tool is listed more than once"]
+        );
+
+        for name in ["tool", "bin/../tool", "bin/sub/tool", "bin/..", "bin/"] {
+            let mut escaping = manifest("tool", "1.0.0");
+            escaping.binaries.push(ManifestBinary {
+                name: name.to_string(),
+                sha256: "cd".repeat(32),
+            });
+            assert_eq!(
+                escaping.problems(),
+                [format!("Binary {:?} is not a file name under bin/", name)]
+            );
+        }
+    }
+
+    #[test]
+    fn invocations_must_match_the_binaries() {
+        let mut missing = manifest("tool", "1.0.0");
+        missing.invocation = Invocation::Daemon {
+            binary: "other".to_string(),
+        };
+        assert_eq!(
+            missing.problems(),
+            ["invocation.binary other is not one of the built binaries"]
+        );
+    }
+
+    #[test]
+    fn path_components() {
+        assert!(is_path_component("tool"));
+        assert!(is_path_component("1.0.0"));
+        for value in ["", ".", "..", "/", "/tool", "a/b", "a\\b", "tool/"] {
+            assert!(!is_path_component(value), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn flake_references_are_git_urls() {
+        for (repo_url, expected) in [
+            (
+                "https://github.com/owner/tool",
+                "git+https://github.com/owner/tool?rev=abc",
+            ),
+            (
+                "git@github.com:owner/tool.git",
+                "git+ssh://git@github.com/owner/tool.git?rev=abc",
+            ),
+            ("/srv/tool", "git+file:///srv/tool?rev=abc"),
+            ("file:///srv/tool", "git+file:///srv/tool?rev=abc"),
+        ] {
+            assert_eq!(FlakeReference::locked(repo_url, "abc").url, expected);
+        }
+    }
+}
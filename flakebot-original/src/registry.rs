@@ -0,0 +1,560 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::attestation;
+use crate::config::{RegistryArgs, RegistryCommand};
+use crate::manifest::{self, ManifestBinary, ToolManifest, MANIFEST_FILE_NAME};
+use crate::smoke::ToolKind;
+
+pub const INDEX_FILE_NAME: &str = "index.json";
+pub const ATTESTATION_FILE_NAME: &str = "attestation.intoto.json";
+const TOOLS_DIR_NAME: &str = "tools";
+
+/// `$XDG_DATA_HOME/flakebot/registry`, falling back to `~/.local/share`.
+pub fn default_dir() -> Result<PathBuf, anyhow::Error> {
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(data_home) => PathBuf::from(data_home),
+        None => PathBuf::from(
+            std::env::var_os("HOME")
+                .ok_or_else(|| anyhow::anyhow!("Neither XDG_DATA_HOME nor HOME is set"))?,
+        )
+        .join(".local/share"),
+    };
+    Ok(data_home.join("flakebot").join("registry"))
+}
+
+/// Opens the registry named by the arguments and runs the command.
+pub fn run(registry_args: RegistryArgs) -> Result<(), anyhow::Error> {
+    let registry_dir = match registry_args.registry_dir {
+        Some(registry_dir) => registry_dir,
+        None => default_dir()?,
+    };
+    Registry::open(&registry_dir)?.run(registry_args.command)
+}
+
+/// One registered version of a tool, as stored in the index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub version: String,
+    pub kind: ToolKind,
+    pub flake_url: String,
+    pub rev: String,
+    pub builder: String,
+    pub binaries: Vec<ManifestBinary>,
+    /// Directory holding the entry's files, relative to the registry root
+    pub path: PathBuf,
+    /// Whether a signed provenance attestation was stored with the tool
+    pub attested: bool,
+    /// Unix timestamp of the registration
+    pub registered_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryIndex {
+    tools: Vec<RegistryEntry>,
+}
+
+/// Built tools stored under a directory, one `tools/<name>/<version>`
+/// directory per entry with its binaries, manifest and attestation, listed in
+/// `index.json`.
+pub struct Registry {
+    root: PathBuf,
+    index: RegistryIndex,
+}
+
+impl Registry {
+    pub fn open(root: &Path) -> Result<Registry, anyhow::Error> {
+        std::fs::create_dir_all(root.join(TOOLS_DIR_NAME))
+            .map_err(|e| anyhow::anyhow!("Failed to create registry {}: {}", root.display(), e))?;
+        let index_path = root.join(INDEX_FILE_NAME);
+        let index = if index_path.exists() {
+            let contents = std::fs::read_to_string(&index_path)?;
+            serde_json::from_str(&contents).map_err(|e| {
+                anyhow::anyhow!("{} is not a valid index: {}", index_path.display(), e)
+            })?
+        } else {
+            RegistryIndex::default()
+        };
+        // Entry paths are deleted by `remove` and `gc`, never trust them blindly
+        for entry in &index.tools {
+            if entry_path(&entry.name, &entry.version)? != entry.path {
+                return Err(anyhow::anyhow!(
+                    "{} lists {} {} at {} instead of under {}/",
+                    index_path.display(),
+                    entry.name,
+                    entry.version,
+                    entry.path.display(),
+                    TOOLS_DIR_NAME
+                ));
+            }
+        }
+        Ok(Registry {
+            root: root.to_path_buf(),
+            index,
+        })
+    }
+
+    pub fn entries(&self) -> &[RegistryEntry] {
+        &self.index.tools
+    }
+
+    /// Finds a version of a tool, the most recently registered one when no
+    /// version is given.
+    pub fn find(&self, name: &str, version: Option<&str>) -> Option<&RegistryEntry> {
+        self.index
+            .tools
+            .iter()
+            .filter(|entry| entry.name == name)
+            .filter(|entry| version.is_none_or(|version| entry.version == version))
+            .max_by_key(|entry| entry.registered_at)
+    }
+
+    pub fn entry_dir(&self, entry: &RegistryEntry) -> PathBuf {
+        self.root.join(&entry.path)
+    }
+
+    pub fn load_manifest(&self, entry: &RegistryEntry) -> Result<ToolManifest, anyhow::Error> {
+        let manifest_path = self.entry_dir(entry).join(MANIFEST_FILE_NAME);
+        let contents = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", manifest_path.display(), e))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Copies the manifest's binaries out of `bin_dir`, checking them against
+    /// the manifest hashes, and records the tool, replacing any entry with the
+    /// same name and version.
+    pub fn register(
+        &mut self,
+        manifest: &ToolManifest,
+        bin_dir: &Path,
+        attestation_path: Option<&Path>,
+    ) -> Result<RegistryEntry, anyhow::Error> {
+        let problems = manifest.problems();
+        if !problems.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Refusing to register an invalid manifest: {}",
+                problems.join(", ")
+            ));
+        }
+
+        // `problems` checks these too, but they are about to become paths
+        let path = entry_path(&manifest.name, &manifest.version)?;
+        let file_names = manifest
+            .binaries
+            .iter()
+            .map(binary_file_name)
+            .collect::<Result<Vec<_>, _>>()?;
+        let entry_dir = self.root.join(&path);
+        if entry_dir.exists() {
+            std::fs::remove_dir_all(&entry_dir)?;
+        }
+        std::fs::create_dir_all(entry_dir.join("bin"))?;
+
+        for (binary, file_name) in manifest.binaries.iter().zip(file_names) {
+            let source = bin_dir.join(file_name);
+            let sha256 = attestation::sha256_file(&source)?;
+            if sha256 != binary.sha256 {
+                std::fs::remove_dir_all(&entry_dir)?;
+                return Err(anyhow::anyhow!(
+                    "{} does not match the manifest hash: {} != {}",
+                    source.display(),
+                    sha256,
+                    binary.sha256
+                ));
+            }
+            let destination = entry_dir.join("bin").join(file_name);
+            std::fs::copy(&source, &destination)?;
+            std::fs::set_permissions(&destination, std::fs::Permissions::from_mode(0o755))?;
+        }
+        manifest.write(&entry_dir.join(MANIFEST_FILE_NAME))?;
+        if let Some(attestation_path) = attestation_path {
+            std::fs::copy(attestation_path, entry_dir.join(ATTESTATION_FILE_NAME))?;
+        }
+
+        let entry = RegistryEntry {
+            name: manifest.name.clone(),
+            version: manifest.version.clone(),
+            kind: manifest.tests.kind,
+            flake_url: manifest.flake.url.clone(),
+            rev: manifest.flake.rev.clone(),
+            builder: manifest.builder.clone(),
+            binaries: manifest.binaries.clone(),
+            path,
+            attested: attestation_path.is_some(),
+            registered_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        self.index
+            .tools
+            .retain(|existing| existing.name != entry.name || existing.version != entry.version);
+        self.index.tools.push(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Removes one version of a tool, or every version when none is given.
+    pub fn remove(
+        &mut self,
+        name: &str,
+        version: Option<&str>,
+    ) -> Result<Vec<RegistryEntry>, anyhow::Error> {
+        let (removed, kept): (Vec<_>, Vec<_>) = self.index.tools.drain(..).partition(|entry| {
+            entry.name == name && version.is_none_or(|version| entry.version == version)
+        });
+        self.index.tools = kept;
+        for entry in &removed {
+            let entry_dir = self.entry_dir(entry);
+            if entry_dir.exists() {
+                std::fs::remove_dir_all(&entry_dir)?;
+            }
+        }
+        self.remove_empty_tool_dirs()?;
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Drops entries whose binaries are missing or no longer match their
+    /// hashes, the oldest versions beyond `keep` for each tool, and any
+    /// directory under `tools/` that no entry refers to. Returns the removed
+    /// paths.
+    pub fn gc(&mut self, keep: Option<usize>) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let mut removed = Vec::new();
+
+        let mut kept = Vec::new();
+        for entry in std::mem::take(&mut self.index.tools) {
+            match self.check_entry(&entry) {
+                Ok(()) => kept.push(entry),
+                Err(e) => {
+                    warn!("Dropping {} {}: {}", entry.name, entry.version, e);
+                    removed.push(entry.path);
+                }
+            }
+        }
+
+        if let Some(keep) = keep {
+            let mut by_name: BTreeMap<String, Vec<RegistryEntry>> = BTreeMap::new();
+            for entry in kept.drain(..) {
+                by_name.entry(entry.name.clone()).or_default().push(entry);
+            }
+            for mut versions in by_name.into_values() {
+                versions.sort_by_key(|entry| std::cmp::Reverse(entry.registered_at));
+                for entry in versions.split_off(keep.min(versions.len())) {
+                    removed.push(entry.path);
+                }
+                kept.extend(versions);
+            }
+        }
+        self.index.tools = kept;
+
+        let referenced: BTreeSet<&Path> = self
+            .index
+            .tools
+            .iter()
+            .map(|entry| entry.path.as_path())
+            .collect();
+        let tools_dir = self.root.join(TOOLS_DIR_NAME);
+        for tool in std::fs::read_dir(&tools_dir)? {
+            let tool_path = tool?.path();
+            if !tool_path.is_dir() {
+                continue;
+            }
+            for version in std::fs::read_dir(&tool_path)? {
+                let version_path = version?.path();
+                let relative = version_path.strip_prefix(&self.root)?;
+                if !referenced.contains(relative) && !removed.iter().any(|path| path == relative) {
+                    removed.push(relative.to_path_buf());
+                }
+            }
+        }
+
+        for path in &removed {
+            let full_path = self.root.join(path);
+            if full_path.is_dir() {
+                std::fs::remove_dir_all(&full_path)?;
+            } else if full_path.exists() {
+                std::fs::remove_file(&full_path)?;
+            }
+        }
+        self.remove_empty_tool_dirs()?;
+        self.save()?;
+        Ok(removed)
+    }
+
+    pub fn run(&mut self, command: RegistryCommand) -> Result<(), anyhow::Error> {
+        match command {
+            RegistryCommand::List { json } => {
+                if json {
+                    println!("{}", serde_json::to_string_pretty(self.entries())?);
+                    return Ok(());
+                }
+                for entry in self.entries() {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        entry.name,
+                        entry.version,
+                        entry.kind,
+                        entry.builder,
+                        self.entry_dir(entry).display()
+                    );
+                }
+                Ok(())
+            }
+            RegistryCommand::Show { name, version } => {
+                let entry = self
+                    .find(&name, version.as_deref())
+                    .ok_or_else(|| anyhow::anyhow!("{} is not in the registry", name))?;
+                info!(
+                    "{} {} at {}",
+                    entry.name,
+                    entry.version,
+                    self.entry_dir(entry).display()
+                );
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&self.load_manifest(entry)?)?
+                );
+                Ok(())
+            }
+            RegistryCommand::Remove { name, version } => {
+                let removed = self.remove(&name, version.as_deref())?;
+                if removed.is_empty() {
+                    return Err(anyhow::anyhow!("{} is not in the registry", name));
+                }
+                for entry in removed {
+                    info!("Removed {} {}", entry.name, entry.version);
+                }
+                Ok(())
+            }
+            RegistryCommand::Gc { keep } => {
+                let removed = self.gc(keep)?;
+                info!("Removed {} path(s) from the registry", removed.len());
+                for path in removed {
+                    info!("Removed {}", path.display());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn check_entry(&self, entry: &RegistryEntry) -> Result<(), anyhow::Error> {
+        let entry_dir = self.entry_dir(entry);
+        if !entry_dir.join(MANIFEST_FILE_NAME).exists() {
+            return Err(anyhow::anyhow!("manifest is missing"));
+        }
+        for binary in &entry.binaries {
+            let path = entry_dir.join("bin").join(binary_file_name(binary)?);
+            if attestation::sha256_file(&path)? != binary.sha256 {
+                return Err(anyhow::anyhow!("{} does not match its hash", binary.name));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_empty_tool_dirs(&self) -> Result<(), anyhow::Error> {
+        for tool in std::fs::read_dir(self.root.join(TOOLS_DIR_NAME))? {
+            let tool_path = tool?.path();
+            if tool_path.is_dir() && std::fs::read_dir(&tool_path)?.next().is_none() {
+                std::fs::remove_dir(&tool_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the index through a temporary file so concurrent readers never
+    /// see a partial index.
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let index_path = self.root.join(INDEX_FILE_NAME);
+        let temp_path = index_path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(&self.index)?)?;
+        std::fs::rename(&temp_path, &index_path)?;
+        Ok(())
+    }
+}
+
+/// `tools/<name>/<version>`, refusing names and versions that would point
+/// anywhere else.
+fn entry_path(name: &str, version: &str) -> Result<PathBuf, anyhow::Error> {
+    for component in [name, version] {
+        if !manifest::is_path_component(component) {
+            return Err(anyhow::anyhow!(
+                "{:?} cannot be used as a registry directory name",
+                component
+            ));
+        }
+    }
+    Ok(Path::new(TOOLS_DIR_NAME).join(name).join(version))
+}
+
+/// The file name of a `bin/<name>` binary inside an entry's `bin` directory.
+fn binary_file_name(binary: &ManifestBinary) -> Result<&str, anyhow::Error> {
+    binary
+        .name
+        .strip_prefix("bin/")
+        .filter(|file_name| manifest::is_path_component(file_name))
+        .ok_or_else(|| anyhow::anyhow!("Binary {:?} is not a file name under bin/", binary.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::manifest::tests::manifest;
+    use crate::test_util::test_dir;
+
+    /// A manifest for `name` and `version` whose `tool` binary is written to
+    /// `bin_dir`.
+    fn built_tool(bin_dir: &Path, name: &str, version: &str) -> ToolManifest {
+        std::fs::create_dir_all(bin_dir).unwrap();
+        let contents = format!("{} {}", name, version);
+        std::fs::write(bin_dir.join("tool"), &contents).unwrap();
+        let mut manifest = manifest(name, version);
+        manifest.binaries[0].sha256 = hex::encode(Sha256::digest(contents));
+        manifest
+    }
+
+    fn names_and_versions(registry: &Registry) -> Vec<(&str, &str)> {
+        registry
+            .entries()
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.version.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn registered_tools_are_copied_and_indexed() {
+        let dir = test_dir("registry-register");
+        let bin_dir = dir.join("bin");
+        let mut registry = Registry::open(&dir.join("registry")).unwrap();
+        let entry = registry
+            .register(&built_tool(&bin_dir, "tool", "1.0.0"), &bin_dir, None)
+            .unwrap();
+        assert_eq!(entry.path, Path::new("tools/tool/1.0.0"));
+        assert!(!entry.attested);
+
+        let registry = Registry::open(&dir.join("registry")).unwrap();
+        let entry = registry.find("tool", None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(registry.entry_dir(entry).join("bin/tool")).unwrap(),
+            "tool 1.0.0"
+        );
+        assert_eq!(registry.load_manifest(entry).unwrap().version, "1.0.0");
+    }
+
+    #[test]
+    fn binaries_must_match_the_manifest() {
+        let dir = test_dir("registry-mismatch");
+        let bin_dir = dir.join("bin");
+        let mut registry = Registry::open(&dir.join("registry")).unwrap();
+        let manifest = built_tool(&bin_dir, "tool", "1.0.0");
+        std::fs::write(bin_dir.join("tool"), "tampered").unwrap();
+        assert!(registry.register(&manifest, &bin_dir, None).is_err());
+        assert!(registry.entries().is_empty());
+        assert!(!dir.join("registry/tools/tool/1.0.0").exists());
+    }
+
+    #[test]
+    fn names_outside_the_registry_are_rejected() {
+        let dir = test_dir("registry-traversal");
+        let bin_dir = dir.join("bin");
+        let victim = dir.join("victim");
+        std::fs::create_dir_all(&victim).unwrap();
+        let mut registry = Registry::open(&dir.join("registry")).unwrap();
+
+        let mut manifests = Vec::new();
+        for (name, version) in [("..", "1.0.0"), ("tool", "../../../victim")] {
+            let mut manifest = built_tool(&bin_dir, "tool", "1.0.0");
+            manifest.name = name.to_string();
+            manifest.version = version.to_string();
+            manifests.push(manifest);
+        }
+        let mut binary = built_tool(&bin_dir, "tool", "1.0.0");
+        binary.binaries[0].name = "bin/../../victim".to_string();
+        manifests.push(binary);
+
+        for manifest in manifests {
+            assert!(registry.register(&manifest, &bin_dir, None).is_err());
+        }
+        assert!(victim.exists());
+        assert!(entry_path("/etc", "1.0.0").is_err());
+        assert!(entry_path("tool", "/1.0.0").is_err());
+
+        // Nor are entries pointing elsewhere in a tampered index
+        let mut entry = registry
+            .register(&built_tool(&bin_dir, "tool", "1.0.0"), &bin_dir, None)
+            .unwrap();
+        entry.path = PathBuf::from("../victim");
+        registry.index.tools = vec![entry];
+        registry.save().unwrap();
+        assert!(Registry::open(&dir.join("registry")).is_err());
+        assert!(victim.exists());
+    }
+
+    #[test]
+    fn removing_one_or_every_version() {
+        let dir = test_dir("registry-remove");
+        let bin_dir = dir.join("bin");
+        let mut registry = Registry::open(&dir.join("registry")).unwrap();
+        for (name, version) in [("tool", "1.0.0"), ("tool", "2.0.0"), ("other", "1.0.0")] {
+            registry
+                .register(&built_tool(&bin_dir, name, version), &bin_dir, None)
+                .unwrap();
+        }
+
+        let removed = registry.remove("tool", Some("1.0.0")).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(!dir.join("registry/tools/tool/1.0.0").exists());
+        assert_eq!(
+            names_and_versions(&registry),
+            [("tool", "2.0.0"), ("other", "1.0.0")]
+        );
+
+        assert_eq!(registry.remove("tool", None).unwrap().len(), 1);
+        assert!(!dir.join("registry/tools/tool").exists());
+        assert!(registry.remove("missing", None).unwrap().is_empty());
+        assert_eq!(names_and_versions(&registry), [("other", "1.0.0")]);
+    }
+
+    #[test]
+    fn gc_drops_broken_old_and_unreferenced_entries() {
+        let dir = test_dir("registry-gc");
+        let bin_dir = dir.join("bin");
+        let root = dir.join("registry");
+        let mut registry = Registry::open(&root).unwrap();
+        for version in ["1.0.0", "2.0.0", "3.0.0"] {
+            registry
+                .register(&built_tool(&bin_dir, "tool", version), &bin_dir, None)
+                .unwrap();
+        }
+        registry
+            .register(&built_tool(&bin_dir, "broken", "1.0.0"), &bin_dir, None)
+            .unwrap();
+        for (entry, registered_at) in registry.index.tools.iter_mut().zip(1..) {
+            entry.registered_at = registered_at;
+        }
+        std::fs::write(root.join("tools/broken/1.0.0/bin/tool"), "tampered").unwrap();
+        std::fs::create_dir_all(root.join("tools/stray/0.1.0")).unwrap();
+
+        let mut removed = registry.gc(Some(2)).unwrap();
+        removed.sort();
+        assert_eq!(
+            removed,
+            [
+                PathBuf::from("tools/broken/1.0.0"),
+                PathBuf::from("tools/stray/0.1.0"),
+                PathBuf::from("tools/tool/1.0.0"),
+            ]
+        );
+        let mut kept = names_and_versions(&registry);
+        kept.sort();
+        assert_eq!(kept, [("tool", "2.0.0"), ("tool", "3.0.0")]);
+        for removed in ["tools/broken", "tools/stray", "tools/tool/1.0.0"] {
+            assert!(!root.join(removed).exists(), "{}", removed);
+        }
+    }
+}
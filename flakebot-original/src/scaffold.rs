@@ -0,0 +1,264 @@
+use std::path::Path;
+
+use fs_extra::dir::{self, CopyOptions};
+use tokio::process::Command;
+use tracing::info;
+
+/// Entries of a template directory that are not copied into the new crate.
+const SKIPPED_TEMPLATE_ENTRIES: [&str; 4] = ["Cargo.toml", "Cargo.lock", "target", ".git"];
+
+/// Manifest tables copied from the template into the new crate.
+const TEMPLATE_TABLES: [&str; 4] = [
+    "dependencies",
+    "dev-dependencies",
+    "build-dependencies",
+    "features",
+];
+
+/// Creates the binary crate `name` in `crate_dir` with `cargo new`, as a git
+/// repository. With a `template` crate its files replace the generated ones
+/// and its dependencies are added to the new manifest.
+pub async fn scaffold(
+    name: &str,
+    crate_dir: &Path,
+    template: Option<&Path>,
+    description: &str,
+) -> Result<(), anyhow::Error> {
+    if crate_dir.exists() {
+        return Err(anyhow::anyhow!(
+            "{} already exists, pass --repo-path {} to work in it",
+            crate_dir.display(),
+            crate_dir.display()
+        ));
+    }
+
+    info!("Scaffolding {} in {}...", name, crate_dir.display());
+    let status = Command::new("cargo")
+        .args(["new", "--bin", "--vcs", "git", "--name", name])
+        .arg(crate_dir)
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("cargo new failed for {}", name));
+    }
+
+    if let Some(template) = template {
+        copy_template(template, crate_dir)?;
+    }
+
+    // The flake stage describes the crate from its README
+    let readme_path = crate_dir.join("README.md");
+    if !readme_path.exists() {
+        std::fs::write(readme_path, format!("# {}\n\n{}\n", name, description))?;
+    }
+    Ok(())
+}
+
+fn copy_template(template: &Path, crate_dir: &Path) -> Result<(), anyhow::Error> {
+    let template = template
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Failed to open template {}: {}", template.display(), e))?;
+    info!("Copying template {}...", template.display());
+
+    for entry in std::fs::read_dir(&template)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if SKIPPED_TEMPLATE_ENTRIES
+            .iter()
+            .any(|skipped| file_name == *skipped)
+        {
+            continue;
+        }
+        let destination = crate_dir.join(&file_name);
+        if entry.file_type()?.is_dir() {
+            if destination.exists() {
+                std::fs::remove_dir_all(&destination)?;
+            }
+            dir::copy(entry.path(), crate_dir, &CopyOptions::new())?;
+        } else {
+            std::fs::copy(entry.path(), destination)?;
+        }
+    }
+
+    let template_manifest = read_manifest(&template.join("Cargo.toml"))?;
+    let workspace_dependencies = workspace_dependencies(&template)?;
+    let manifest_path = crate_dir.join("Cargo.toml");
+    let mut manifest = read_manifest(&manifest_path)?;
+    for table_name in TEMPLATE_TABLES {
+        let Some(toml::Value::Table(table)) = template_manifest.get(table_name) else {
+            continue;
+        };
+        let mut table = table.clone();
+        if table_name != "features" {
+            for (name, spec) in table.iter_mut() {
+                rebase_path(spec, &template);
+                *spec = inherit_dependency(name, spec, &workspace_dependencies)?;
+            }
+        }
+        manifest.insert(table_name.to_string(), toml::Value::Table(table));
+    }
+    std::fs::write(&manifest_path, toml::to_string(&manifest)?)?;
+    Ok(())
+}
+
+fn read_manifest(path: &Path) -> Result<toml::Table, anyhow::Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("{} is not valid TOML: {}", path.display(), e))
+}
+
+/// `[workspace.dependencies]` of the workspace the template belongs to, so
+/// the new crate, which is outside of it, can inherit them.
+fn workspace_dependencies(template: &Path) -> Result<toml::Table, anyhow::Error> {
+    for dir in template.ancestors().skip(1) {
+        let manifest_path = dir.join("Cargo.toml");
+        if !manifest_path.exists() {
+            continue;
+        }
+        let manifest = read_manifest(&manifest_path)?;
+        if let Some(workspace) = manifest.get("workspace") {
+            let mut dependencies = workspace
+                .get("dependencies")
+                .and_then(toml::Value::as_table)
+                .cloned()
+                .unwrap_or_default();
+            for (_, spec) in dependencies.iter_mut() {
+                rebase_path(spec, dir);
+            }
+            return Ok(dependencies);
+        }
+    }
+    Ok(toml::Table::new())
+}
+
+/// Makes the `path` of a dependency declared in the manifest in `dir`
+/// absolute, as the new crate is created elsewhere.
+fn rebase_path(spec: &mut toml::Value, dir: &Path) {
+    let Some(path) = spec.get_mut("path") else {
+        return;
+    };
+    if let Some(relative) = path.as_str().filter(|path| Path::new(path).is_relative()) {
+        *path = toml::Value::from(dir.join(relative).to_string_lossy().as_ref());
+    }
+}
+
+/// Replaces `{ workspace = true, .. }` with the workspace's specification,
+/// keeping the keys the template adds such as `features`.
+fn inherit_dependency(
+    name: &str,
+    spec: &toml::Value,
+    workspace_dependencies: &toml::Table,
+) -> Result<toml::Value, anyhow::Error> {
+    let Some(table) = spec.as_table() else {
+        return Ok(spec.clone());
+    };
+    if table.get("workspace").and_then(toml::Value::as_bool) != Some(true) {
+        return Ok(spec.clone());
+    }
+    let mut inherited = match workspace_dependencies.get(name) {
+        Some(toml::Value::String(version)) => {
+            toml::Table::from_iter([("version".to_string(), toml::Value::from(version.as_str()))])
+        }
+        Some(toml::Value::Table(inherited)) => inherited.clone(),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Template dependency {} is inherited from a workspace that does not define it",
+                name
+            ))
+        }
+    };
+    for (key, value) in table {
+        match (key.as_str(), inherited.get_mut(key), value) {
+            ("workspace", _, _) => {}
+            ("features", Some(toml::Value::Array(features)), toml::Value::Array(extra)) => {
+                features.extend(extra.iter().cloned())
+            }
+            _ => {
+                inherited.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Ok(toml::Value::Table(inherited))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    fn table(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn workspace() -> toml::Table {
+        table(
+            r#"
+            serde = "1.0"
+            tokio = { version = "1", features = ["rt"] }
+            "#,
+        )
+    }
+
+    #[test]
+    fn dependencies_not_from_the_workspace_are_kept() {
+        for spec in [
+            toml::Value::from("0.3"),
+            toml::Value::Table(table("version = \"0.3\"\noptional = true")),
+        ] {
+            assert_eq!(
+                inherit_dependency("serde", &spec, &workspace()).unwrap(),
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn workspace_dependencies_are_inherited() {
+        let spec = toml::Value::Table(table("workspace = true\noptional = true"));
+        assert_eq!(
+            inherit_dependency("serde", &spec, &workspace()).unwrap(),
+            toml::Value::Table(table("version = \"1.0\"\noptional = true"))
+        );
+        let spec = toml::Value::Table(table("workspace = true\nfeatures = [\"macros\"]"));
+        assert_eq!(
+            inherit_dependency("tokio", &spec, &workspace()).unwrap(),
+            toml::Value::Table(table("version = \"1\"\nfeatures = [\"rt\", \"macros\"]"))
+        );
+    }
+
+    #[test]
+    fn missing_workspace_dependencies_are_errors() {
+        let spec = toml::Value::Table(table("workspace = true"));
+        let error = inherit_dependency("axum", &spec, &workspace()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Template dependency axum is inherited from a workspace that does not define it"
+        );
+    }
+
+    #[test]
+    fn workspace_paths_are_rebased_onto_the_workspace() {
+        let dir = test_dir("scaffold-workspace");
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            r#"
+            [workspace]
+            members = ["template"]
+            [workspace.dependencies]
+            shared = { path = "shared" }
+            pinned = { path = "/opt/pinned" }
+            "#,
+        )
+        .unwrap();
+        let template = dir.join("template");
+        std::fs::create_dir_all(&template).unwrap();
+
+        let dependencies = workspace_dependencies(&template).unwrap();
+        assert_eq!(
+            dependencies["shared"]["path"].as_str(),
+            Some(dir.join("shared").to_str().unwrap())
+        );
+        assert_eq!(dependencies["pinned"]["path"].as_str(), Some("/opt/pinned"));
+    }
+}
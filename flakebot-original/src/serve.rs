@@ -0,0 +1,798 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{Client, Method};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::config::ServeArgs;
+use crate::manifest::Invocation;
+use crate::registry::{self, Registry};
+use crate::smoke;
+
+/// Model Context Protocol revision implemented by `flakebot serve`.
+pub const MCP_PROTOCOL_VERSION: &str = "2024-11-05";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// HTTP methods exposed as tools for each OpenAPI path.
+const HTTP_METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+/// How deep `$ref`s of an OpenAPI document are inlined into input schemas.
+const MAX_REF_DEPTH: usize = 16;
+/// Everything but the unreserved characters of RFC 3986 is escaped in path
+/// parameters, so arguments cannot add segments or a query.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Transport {
+    /// Newline-delimited JSON-RPC over stdin and stdout
+    Stdio,
+    /// JSON-RPC requests POSTed to /mcp
+    Http,
+}
+
+/// A registered tool as offered to MCP clients.
+struct ServedTool {
+    name: String,
+    description: String,
+    input_schema: Value,
+    target: ToolTarget,
+}
+
+enum ToolTarget {
+    /// Run the binary once per call with the given arguments and stdin
+    Cli { binary: PathBuf },
+    /// Proxy the call to one operation of a server started on demand
+    Http {
+        server: String,
+        binary: PathBuf,
+        port: u16,
+        method: Method,
+        path: String,
+        parameters: Vec<HttpParameter>,
+    },
+}
+
+struct HttpParameter {
+    name: String,
+    in_path: bool,
+}
+
+#[derive(Deserialize)]
+struct JsonRpcRequest {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Serves the tools of a registry over MCP. CLI tools become one tool each,
+/// HTTP tools one tool per OpenAPI operation; daemons and libraries have no
+/// callable interface and are left out.
+pub struct ToolServer {
+    tools: Vec<ServedTool>,
+    call_timeout: Duration,
+    startup_timeout: Duration,
+    client: Client,
+    servers: Mutex<HashMap<String, Child>>,
+}
+
+impl ToolServer {
+    /// Loads the most recent version of every registered tool, or only of the
+    /// named ones when `only` is not empty.
+    pub fn from_registry(
+        registry: &Registry,
+        only: &[String],
+        call_timeout: Duration,
+        startup_timeout: Duration,
+    ) -> Result<ToolServer, anyhow::Error> {
+        let mut names: Vec<&str> = registry
+            .entries()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .filter(|name| only.is_empty() || only.iter().any(|only| only == name))
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in only {
+            if !names.contains(&name.as_str()) {
+                return Err(anyhow::anyhow!("{} is not in the registry", name));
+            }
+        }
+
+        let mut tools = Vec::new();
+        for name in names {
+            let entry = registry
+                .find(name, None)
+                .ok_or_else(|| anyhow::anyhow!("{} is not in the registry", name))?;
+            let manifest = registry.load_manifest(entry)?;
+            let bin_dir = registry.entry_dir(entry).join("bin");
+            let description = manifest
+                .description
+                .clone()
+                .unwrap_or_else(|| manifest.name.clone());
+            match manifest.invocation {
+                Invocation::Cli {
+                    binary,
+                    args_schema,
+                } => tools.push(ServedTool {
+                    name: tool_name(&[&manifest.name]),
+                    description: format!(
+                        "{}\n\nArguments of {}: {}",
+                        description, binary, args_schema
+                    ),
+                    input_schema: json!({
+                        "type": "object",
+                        "properties": {
+                            "args": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": format!("Command line arguments passed to {}", binary),
+                            },
+                            "stdin": {
+                                "type": "string",
+                                "description": "Text written to the tool's standard input",
+                            },
+                        },
+                    }),
+                    target: ToolTarget::Cli {
+                        binary: bin_dir.join(&binary),
+                    },
+                }),
+                Invocation::Http {
+                    binary,
+                    port,
+                    openapi,
+                } => {
+                    let port = port.ok_or_else(|| {
+                        anyhow::anyhow!("{} does not record the port it listens on", manifest.name)
+                    })?;
+                    tools.extend(http_tools(
+                        &manifest.name,
+                        &bin_dir.join(&binary),
+                        port,
+                        &openapi,
+                    ));
+                }
+                Invocation::Daemon { .. } | Invocation::Library { .. } => {
+                    info!(
+                        "Skipping {}, {} tools cannot be called",
+                        manifest.name, manifest.tests.kind
+                    );
+                }
+            }
+        }
+        info!("Serving {} tool(s)", tools.len());
+
+        Ok(ToolServer {
+            tools,
+            call_timeout,
+            startup_timeout,
+            client: Client::new(),
+            servers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Handles one JSON-RPC message, returning the response or `None` for
+    /// notifications.
+    pub async fn handle_message(&self, message: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(message) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let request = match serde_json::from_value::<JsonRpcRequest>(request) {
+            Ok(request) if request.jsonrpc == "2.0" => request,
+            Ok(_) => return Some(error_response(id, INVALID_REQUEST, "jsonrpc must be 2.0")),
+            Err(e) => return Some(error_response(id, INVALID_REQUEST, &e.to_string())),
+        };
+        // Requests without an id are notifications and get no response
+        let id = request.id?;
+
+        let result = match request.method.as_str() {
+            "initialize" => Ok(json!({
+                "protocolVersion": MCP_PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "flakebot", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": self.list_tools() })),
+            "tools/call" => self.call_tool(&request.params).await,
+            method => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        };
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    fn list_tools(&self) -> Vec<Value> {
+        self.tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "name": tool.name,
+                    "description": tool.description,
+                    "inputSchema": tool.input_schema,
+                })
+            })
+            .collect()
+    }
+
+    /// Runs a tool. Failures of the tool itself are reported in the result
+    /// with `isError` so the model can see them.
+    async fn call_tool(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| (INVALID_PARAMS, "Missing tool name".to_string()))?;
+        let tool = self
+            .tools
+            .iter()
+            .find(|tool| tool.name == name)
+            .ok_or_else(|| (INVALID_PARAMS, format!("Unknown tool {}", name)))?;
+        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+
+        info!("Calling {}", tool.name);
+        let outcome = match &tool.target {
+            ToolTarget::Cli { binary } => self.run_cli(binary, &arguments).await,
+            ToolTarget::Http { .. } => self.proxy_http(&tool.target, &arguments).await,
+        };
+        let (text, is_error) = match outcome {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                warn!("{} failed: {}", tool.name, e);
+                (e.to_string(), true)
+            }
+        };
+        Ok(json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error,
+        }))
+    }
+
+    async fn run_cli(
+        &self,
+        binary: &Path,
+        arguments: &Value,
+    ) -> Result<(String, bool), anyhow::Error> {
+        let args: Vec<String> = match arguments.get("args") {
+            Some(args) => serde_json::from_value(args.clone())
+                .map_err(|e| anyhow::anyhow!("args must be a list of strings: {}", e))?,
+            None => Vec::new(),
+        };
+        let mut child = Command::new(binary)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Failed to open stdin"))?;
+        if let Some(input) = arguments.get("stdin").and_then(Value::as_str) {
+            stdin.write_all(input.as_bytes()).await?;
+        }
+        drop(stdin);
+
+        let output = tokio::time::timeout(self.call_timeout, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out after {:?}", self.call_timeout))??;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        if output.status.success() {
+            return Ok((stdout, false));
+        }
+        Ok((
+            format!(
+                "Exited with {}\n{}{}",
+                output.status,
+                stdout,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            true,
+        ))
+    }
+
+    async fn proxy_http(
+        &self,
+        target: &ToolTarget,
+        arguments: &Value,
+    ) -> Result<(String, bool), anyhow::Error> {
+        let ToolTarget::Http {
+            server,
+            binary,
+            port,
+            method,
+            path,
+            parameters,
+        } = target
+        else {
+            return Err(anyhow::anyhow!("Not an HTTP tool"));
+        };
+        let (url_path, query) = request_path(path, parameters, arguments)?;
+        self.ensure_server(server, binary, *port).await?;
+
+        let mut request = self
+            .client
+            .request(
+                method.clone(),
+                format!("http://127.0.0.1:{}{}", port, url_path),
+            )
+            .query(&query)
+            .timeout(self.call_timeout);
+        if let Some(body) = arguments.get("body") {
+            request = request.json(body);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        Ok((format!("HTTP {}\n{}", status, body), !status.is_success()))
+    }
+
+    /// Starts the server of an HTTP tool unless it is already running.
+    async fn ensure_server(
+        &self,
+        server: &str,
+        binary: &Path,
+        port: u16,
+    ) -> Result<(), anyhow::Error> {
+        let mut servers = self.servers.lock().await;
+        if let Some(child) = servers.get_mut(server) {
+            if child.try_wait()?.is_none() {
+                return Ok(());
+            }
+            warn!("{} exited, restarting it", server);
+        }
+
+        info!("Starting {} on port {}", server, port);
+        // The server's output must not end up on the stdio transport
+        let mut child = Command::new(binary)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        smoke::wait_for_port(&mut child, port, self.startup_timeout).await?;
+        servers.insert(server.to_string(), child);
+        Ok(())
+    }
+}
+
+/// Fills the path parameters of an OpenAPI path template with the percent
+/// encoded arguments and returns it with the query parameters.
+fn request_path(
+    path: &str,
+    parameters: &[HttpParameter],
+    arguments: &Value,
+) -> Result<(String, Vec<(String, String)>), anyhow::Error> {
+    let mut url_path = path.to_string();
+    let mut query = Vec::new();
+    for parameter in parameters {
+        let Some(value) = arguments.get(&parameter.name) else {
+            continue;
+        };
+        let value = match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        if parameter.in_path {
+            // Dot segments are resolved by the URL parser even when escaped
+            if value.is_empty() || value == "." || value == ".." {
+                return Err(anyhow::anyhow!(
+                    "{} is not a valid value for the path parameter {}",
+                    value,
+                    parameter.name
+                ));
+            }
+            let encoded = utf8_percent_encode(&value, PATH_SEGMENT).to_string();
+            url_path = url_path.replace(&format!("{{{}}}", parameter.name), &encoded);
+        } else {
+            query.push((parameter.name.clone(), value));
+        }
+    }
+    Ok((url_path, query))
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// MCP tool names may only contain letters, digits, `_` and `-`.
+fn tool_name(parts: &[&str]) -> String {
+    parts
+        .join("_")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect()
+}
+
+/// Turns every operation of an OpenAPI document into a tool whose input
+/// schema has a property per path or query parameter and a `body` property
+/// for the JSON request body.
+fn http_tools(name: &str, binary: &Path, port: u16, openapi: &Value) -> Vec<ServedTool> {
+    let Some(paths) = openapi.get("paths").and_then(Value::as_object) else {
+        warn!("{} has no paths in its OpenAPI document", name);
+        return Vec::new();
+    };
+
+    let mut tools = Vec::new();
+    for (path, item) in paths {
+        for http_method in HTTP_METHODS {
+            let Some(operation) = item.get(http_method) else {
+                continue;
+            };
+            let method_path = format!("{} {}", http_method.to_uppercase(), path);
+            let operation_name = operation
+                .get("operationId")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{}{}", http_method, path.replace('/', "_")));
+            let summary = operation
+                .get("summary")
+                .or_else(|| operation.get("description"))
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+
+            let mut properties = serde_json::Map::new();
+            let mut required = Vec::new();
+            let mut parameters = Vec::new();
+            let declared = item
+                .get("parameters")
+                .and_then(Value::as_array)
+                .into_iter()
+                .chain(operation.get("parameters").and_then(Value::as_array))
+                .flatten();
+            for parameter in declared {
+                let parameter = resolve_refs(parameter, openapi, 0);
+                let (Some(parameter_name), Some(location)) = (
+                    parameter.get("name").and_then(Value::as_str),
+                    parameter.get("in").and_then(Value::as_str),
+                ) else {
+                    continue;
+                };
+                if location != "path" && location != "query" {
+                    continue;
+                }
+                let mut schema = parameter.get("schema").cloned().unwrap_or(json!({}));
+                if let Some(description) = parameter.get("description") {
+                    schema["description"] = description.clone();
+                }
+                properties.insert(parameter_name.to_string(), schema);
+                if location == "path" || parameter.get("required") == Some(&json!(true)) {
+                    required.push(json!(parameter_name));
+                }
+                parameters.push(HttpParameter {
+                    name: parameter_name.to_string(),
+                    in_path: location == "path",
+                });
+            }
+            if let Some(body) = operation.get("requestBody") {
+                let body = resolve_refs(body, openapi, 0);
+                if let Some(schema) = body.pointer("/content/application~1json/schema") {
+                    properties.insert("body".to_string(), schema.clone());
+                    if body.get("required") == Some(&json!(true)) {
+                        required.push(json!("body"));
+                    }
+                }
+            }
+
+            tools.push(ServedTool {
+                name: tool_name(&[name, &operation_name]),
+                description: format!("{}: {} {}", name, method_path, summary)
+                    .trim_end()
+                    .to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": properties,
+                    "required": required,
+                }),
+                target: ToolTarget::Http {
+                    server: name.to_string(),
+                    binary: binary.to_path_buf(),
+                    port,
+                    method: Method::from_bytes(http_method.to_uppercase().as_bytes())
+                        .unwrap_or(Method::GET),
+                    path: path.clone(),
+                    parameters,
+                },
+            });
+        }
+    }
+    tools
+}
+
+/// Inlines local `$ref`s so the schemas stand alone outside the document.
+fn resolve_refs(value: &Value, document: &Value, depth: usize) -> Value {
+    if depth > MAX_REF_DEPTH {
+        return json!({});
+    }
+    match value {
+        Value::Object(object) => {
+            if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                return match reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| document.pointer(pointer))
+                {
+                    Some(target) => resolve_refs(target, document, depth + 1),
+                    None => json!({}),
+                };
+            }
+            Value::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), resolve_refs(value, document, depth)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| resolve_refs(item, document, depth))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Loads the registry named by the arguments and serves it until the
+/// transport closes.
+pub async fn run(serve_args: ServeArgs) -> Result<(), anyhow::Error> {
+    let registry_dir = match serve_args.registry_dir {
+        Some(registry_dir) => registry_dir,
+        None => registry::default_dir()?,
+    };
+    let server = ToolServer::from_registry(
+        &Registry::open(&registry_dir)?,
+        &serve_args.tools,
+        Duration::from_secs(serve_args.call_timeout),
+        Duration::from_secs(serve_args.startup_timeout),
+    )?;
+    match serve_args.transport {
+        Transport::Stdio => serve_stdio(server).await,
+        Transport::Http => serve_http(server, serve_args.listen).await,
+    }
+}
+
+/// Reads newline-delimited JSON-RPC messages from stdin until it closes.
+pub async fn serve_stdio(server: ToolServer) -> Result<(), anyhow::Error> {
+    info!("Serving MCP on stdio");
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle_message(&line).await {
+            stdout
+                .write_all(format!("{}\n", response).as_bytes())
+                .await?;
+            stdout.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Accepts JSON-RPC messages POSTed to `/mcp` and answers with JSON.
+pub async fn serve_http(server: ToolServer, listen: SocketAddr) -> Result<(), anyhow::Error> {
+    let app = Router::new()
+        .route("/mcp", post(handle_http))
+        .with_state(Arc::new(server));
+    let listener = tokio::net::TcpListener::bind(listen).await.map_err(|e| {
+        error!("Failed to listen on {}: {}", listen, e);
+        anyhow::anyhow!("Failed to listen on {}: {}", listen, e)
+    })?;
+    info!("Serving MCP on http://{}/mcp", listen);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn handle_http(State(server): State<Arc<ToolServer>>, body: String) -> Response {
+    match server.handle_message(&body).await {
+        Some(response) => Json(response).into_response(),
+        None => StatusCode::ACCEPTED.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn openapi() -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "paths": {
+                "/task/{id}": {
+                    "parameters": [{ "name": "id", "in": "path", "schema": { "type": "string" } }],
+                    "get": { "operationId": "read_task", "summary": "Read a task" },
+                },
+                "/tasks": {
+                    "post": {
+                        "parameters": [{ "$ref": "#/components/parameters/Verbose" }],
+                        "requestBody": {
+                            "required": true,
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Task" } } },
+                        },
+                    },
+                },
+            },
+            "components": {
+                "parameters": { "Verbose": { "name": "verbose", "in": "query", "schema": { "type": "boolean" } } },
+                "schemas": { "Task": { "type": "object", "properties": { "name": { "type": "string" } } } },
+            },
+        })
+    }
+
+    fn server() -> ToolServer {
+        let mut tools = http_tools("tasks", Path::new("/nonexistent/tasks"), 1, &openapi());
+        tools.push(ServedTool {
+            name: tool_name(&["echo"]),
+            description: "Prints its arguments".to_string(),
+            input_schema: json!({ "type": "object" }),
+            target: ToolTarget::Cli {
+                binary: PathBuf::from("echo"),
+            },
+        });
+        ToolServer {
+            tools,
+            call_timeout: Duration::from_secs(5),
+            startup_timeout: Duration::from_secs(1),
+            client: Client::new(),
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn call(server: &ToolServer, method: &str, params: Value) -> Value {
+        let message = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params });
+        let response = server.handle_message(&message.to_string()).await.unwrap();
+        assert_eq!(response["id"], 7);
+        response
+    }
+
+    #[tokio::test]
+    async fn initialize_reports_the_protocol_and_tools_capability() {
+        let response = call(&server(), "initialize", json!({})).await;
+        assert_eq!(response["result"]["protocolVersion"], MCP_PROTOCOL_VERSION);
+        assert_eq!(response["result"]["capabilities"], json!({ "tools": {} }));
+        assert_eq!(response["result"]["serverInfo"]["name"], "flakebot");
+    }
+
+    #[tokio::test]
+    async fn tools_list_has_a_tool_per_operation() {
+        let response = call(&server(), "tools/list", json!({})).await;
+        let tools = response["result"]["tools"].as_array().unwrap();
+        let names: Vec<_> = tools.iter().map(|tool| tool["name"].clone()).collect();
+        assert_eq!(names, ["tasks_read_task", "tasks_post_tasks", "echo"]);
+
+        assert_eq!(tools[0]["description"], "tasks: GET /task/{id} Read a task");
+        assert_eq!(tools[0]["inputSchema"]["required"], json!(["id"]));
+        assert_eq!(
+            tools[1]["inputSchema"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "verbose": { "type": "boolean" },
+                    "body": { "type": "object", "properties": { "name": { "type": "string" } } },
+                },
+                "required": ["body"],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_methods_and_malformed_messages_are_errors() {
+        let server = server();
+        let response = call(&server, "resources/list", json!({})).await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let response = server.handle_message("{not json").await.unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        let message = json!({ "jsonrpc": "1.0", "id": 1, "method": "ping" });
+        let response = server.handle_message(&message.to_string()).await.unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+
+        let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert!(server
+            .handle_message(&notification.to_string())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn tool_calls_need_a_known_tool_name() {
+        let server = server();
+        for params in [
+            json!({}),
+            json!({ "name": 1 }),
+            json!({ "name": "missing" }),
+        ] {
+            let response = call(&server, "tools/call", params.clone()).await;
+            assert_eq!(response["error"]["code"], INVALID_PARAMS, "{}", params);
+        }
+    }
+
+    #[tokio::test]
+    async fn cli_tools_are_run_with_their_arguments() {
+        let server = server();
+        let params = json!({ "name": "echo", "arguments": { "args": ["hello", "world"] } });
+        let response = call(&server, "tools/call", params).await;
+        assert_eq!(
+            response["result"],
+            json!({ "content": [{ "type": "text", "text": "hello world\n" }], "isError": false })
+        );
+
+        let params = json!({ "name": "echo", "arguments": { "args": "hello" } });
+        let response = call(&server, "tools/call", params).await;
+        assert_eq!(response["result"]["isError"], true);
+    }
+
+    #[test]
+    fn path_parameters_are_percent_encoded() {
+        let parameters = [
+            HttpParameter {
+                name: "id".to_string(),
+                in_path: true,
+            },
+            HttpParameter {
+                name: "verbose".to_string(),
+                in_path: false,
+            },
+        ];
+        let path = |arguments: Value| request_path("/task/{id}", &parameters, &arguments);
+
+        assert_eq!(
+            path(json!({ "id": 3, "verbose": true })).unwrap(),
+            (
+                "/task/3".to_string(),
+                vec![("verbose".to_string(), "true".to_string())]
+            )
+        );
+        assert_eq!(
+            path(json!({ "id": "../admin" })).unwrap().0,
+            "/task/..%2Fadmin"
+        );
+        assert_eq!(
+            path(json!({ "id": "a?x=1#f" })).unwrap().0,
+            "/task/a%3Fx%3D1%23f"
+        );
+        assert_eq!(path(json!({ "id": "ü b" })).unwrap().0, "/task/%C3%BC%20b");
+        for id in ["", ".", ".."] {
+            assert!(path(json!({ "id": id })).is_err(), "{:?}", id);
+        }
+    }
+}
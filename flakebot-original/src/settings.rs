@@ -0,0 +1,416 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::app::Stage;
+use crate::builder::{BuilderKind, DEFAULT_RUST_TOOLCHAIN};
+use crate::config::ConfigCommand;
+use crate::flake::InputOverride;
+use crate::groq::{GROQ_API_BASE_URL, GROQ_BASE_MODEL};
+use crate::smoke::ToolKind;
+
+/// Project configuration file, looked up in the current directory.
+pub const PROJECT_CONFIG_FILE_NAME: &str = "flakebot.toml";
+/// Shown instead of secrets by `config show`.
+pub const REDACTED: &str = "<redacted>";
+
+/// The effective configuration: defaults, overridden by
+/// `~/.config/flakebot/config.toml`, then `flakebot.toml`, then the selected
+/// profile of either file, then environment variables and flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub work_dir: PathBuf,
+    pub groq_api_key: Option<String>,
+    pub github_token: Option<String>,
+    pub cargo_cookie: Option<String>,
+    pub override_inputs: Vec<InputOverride>,
+    pub flake_registry: Option<PathBuf>,
+    /// flake.nix template, the built in reference flake when not set
+    pub reference_flake: Option<PathBuf>,
+    pub attestation_key: Option<PathBuf>,
+    pub binaries: Vec<String>,
+    pub builder: BuilderKind,
+    pub rust_toolchain: String,
+    /// Seconds to wait for a built server to accept connections
+    pub smoke_startup_timeout: u64,
+    /// Seconds a built daemon must keep running
+    pub daemon_uptime: u64,
+    pub tool_kind: Option<ToolKind>,
+    pub registry_dir: Option<PathBuf>,
+    /// Stages run by `craft` and `resume`, every stage when empty
+    pub stages: Vec<Stage>,
+    pub skip: Vec<Stage>,
+    /// Existing checkout to work in instead of forking a crate
+    pub repo_path: Option<PathBuf>,
+    /// Repository URL to fork, or a local checkout, instead of finding a crate
+    pub repo: Option<String>,
+    /// Crate to look up instead of asking the LLM for one
+    #[serde(rename = "crate")]
+    pub crate_name: Option<String>,
+    /// Name of a new crate to create instead of looking one up
+    pub scaffold: Option<String>,
+    /// Crate copied into new crates, which are also created when no crate is
+    /// found
+    pub template: Option<PathBuf>,
+    pub llm: LlmSettings,
+    pub git: GitSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            work_dir: PathBuf::from("./work_dir"),
+            groq_api_key: None,
+            github_token: None,
+            cargo_cookie: None,
+            override_inputs: Vec::new(),
+            flake_registry: None,
+            reference_flake: None,
+            attestation_key: None,
+            binaries: Vec::new(),
+            builder: BuilderKind::Auto,
+            rust_toolchain: DEFAULT_RUST_TOOLCHAIN.to_string(),
+            smoke_startup_timeout: 30,
+            daemon_uptime: 10,
+            tool_kind: None,
+            registry_dir: None,
+            stages: Vec::new(),
+            skip: Vec::new(),
+            repo_path: None,
+            repo: None,
+            crate_name: None,
+            scaffold: None,
+            template: None,
+            llm: LlmSettings::default(),
+            git: GitSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmSettings {
+    pub model: String,
+    /// Base URL of an OpenAI compatible chat completions API
+    pub base_url: String,
+}
+
+impl Default for LlmSettings {
+    fn default() -> Self {
+        LlmSettings {
+            model: GROQ_BASE_MODEL.to_string(),
+            base_url: GROQ_API_BASE_URL.to_string(),
+        }
+    }
+}
+
+/// How the pipeline names its branches and commits in the tool's repository.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitSettings {
+    pub flake_branch: String,
+    pub flakebox_branch: String,
+    pub feature_branch: String,
+    pub commit_author: String,
+    /// Contents written to the repository's `.gitignore`
+    pub gitignore: String,
+}
+
+impl Default for GitSettings {
+    fn default() -> Self {
+        GitSettings {
+            flake_branch: "nix-flake".to_string(),
+            flakebox_branch: "flakebox".to_string(),
+            feature_branch: "new-feature".to_string(),
+            commit_author: "FlakeBot <flakebot@flakebot.com>".to_string(),
+            gitignore: "/target\n/result\n/work_dir\n/db\n/tmp\n/nix\n/result\n".to_string(),
+        }
+    }
+}
+
+impl Settings {
+    /// Merges the configuration files, the profile and `overrides`, which
+    /// hold the values given as flags or environment variables. `config_path`
+    /// replaces `./flakebot.toml` and must exist.
+    pub fn load(
+        config_path: Option<&Path>,
+        profile: Option<&str>,
+        overrides: toml::Table,
+    ) -> Result<Settings, anyhow::Error> {
+        Self::load_layers(
+            user_config_path().as_deref(),
+            config_path,
+            profile,
+            overrides,
+        )
+    }
+
+    fn load_layers(
+        user_config_path: Option<&Path>,
+        config_path: Option<&Path>,
+        profile: Option<&str>,
+        overrides: toml::Table,
+    ) -> Result<Settings, anyhow::Error> {
+        let mut merged = toml::Table::new();
+        if let Some(user_config_path) = user_config_path {
+            merge_file(&mut merged, user_config_path)?;
+        }
+        match config_path {
+            Some(config_path) if !config_path.exists() => {
+                return Err(anyhow::anyhow!(
+                    "Config file {} not found",
+                    config_path.display()
+                ));
+            }
+            Some(config_path) => merge_file(&mut merged, config_path)?,
+            None => merge_file(&mut merged, Path::new(PROJECT_CONFIG_FILE_NAME))?,
+        }
+
+        let profiles = merged.remove("profiles");
+        if let Some(profile) = profile {
+            let profile_table = profiles
+                .as_ref()
+                .and_then(|profiles| profiles.get(profile))
+                .and_then(toml::Value::as_table)
+                .ok_or_else(|| anyhow::anyhow!("Profile {} not found in the config", profile))?;
+            info!("Using profile {}", profile);
+            merge(&mut merged, profile_table.clone());
+        }
+        merge(&mut merged, overrides);
+
+        toml::Value::Table(merged)
+            .try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid configuration: {}", e))
+    }
+
+    /// Returns a secret or explains every way it can be set.
+    pub fn secret<'a>(
+        value: &'a Option<String>,
+        name: &str,
+        env: &str,
+    ) -> Result<&'a str, anyhow::Error> {
+        value.as_deref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is not set, pass --{}, set {} or add it to {}",
+                name,
+                name.replace('_', "-"),
+                env,
+                PROJECT_CONFIG_FILE_NAME
+            )
+        })
+    }
+
+    /// A copy safe to print, with every secret replaced by [`REDACTED`].
+    pub fn redacted(&self) -> Settings {
+        let redact = |secret: &Option<String>| secret.as_ref().map(|_| REDACTED.to_string());
+        Settings {
+            groq_api_key: redact(&self.groq_api_key),
+            github_token: redact(&self.github_token),
+            cargo_cookie: redact(&self.cargo_cookie),
+            ..self.clone()
+        }
+    }
+}
+
+/// Prints the effective configuration as TOML with secrets redacted.
+pub fn run(command: ConfigCommand) -> Result<(), anyhow::Error> {
+    match command {
+        ConfigCommand::Show(craft_args) => {
+            let settings = craft_args.settings()?.redacted();
+            print!("{}", toml::to_string_pretty(&settings)?);
+            Ok(())
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/flakebot/config.toml`, falling back to `~/.config`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) => PathBuf::from(config_home),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("flakebot").join("config.toml"))
+}
+
+fn merge_file(merged: &mut toml::Table, path: &Path) -> Result<(), anyhow::Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let table: toml::Table = toml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("{} is not valid TOML: {}", path.display(), e))?;
+    info!("Loaded config from {}", path.display());
+    merge(merged, table);
+    Ok(())
+}
+
+/// Deep merges `overlay` into `base`: tables are merged key by key, any
+/// other value in `overlay` replaces the one in `base`.
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge(base_table, overlay_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    fn table(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn merge_replaces_scalars_and_merges_tables() {
+        let mut base = table(
+            r#"
+            binaries = ["a", "b"]
+            builder = "nix"
+            [git]
+            flake_branch = "nix"
+            feature_branch = "feature"
+            "#,
+        );
+        merge(
+            &mut base,
+            table(
+                r#"
+                binaries = ["c"]
+                [git]
+                flake_branch = "flake"
+                [llm]
+                model = "other"
+                "#,
+            ),
+        );
+        assert_eq!(
+            base,
+            table(
+                r#"
+                binaries = ["c"]
+                builder = "nix"
+                [git]
+                flake_branch = "flake"
+                feature_branch = "feature"
+                [llm]
+                model = "other"
+                "#,
+            )
+        );
+    }
+
+    #[test]
+    fn layers_apply_in_order() {
+        let dir = test_dir("settings-layers");
+        let user_config = dir.join("config.toml");
+        std::fs::write(
+            &user_config,
+            r#"
+            groq_api_key = "user"
+            rust_toolchain = "1.70.0"
+            smoke_startup_timeout = 5
+            [git]
+            commit_author = "User <user@example.com>"
+            "#,
+        )
+        .unwrap();
+        let project_config = dir.join("flakebot.toml");
+        std::fs::write(
+            &project_config,
+            r#"
+            rust_toolchain = "1.75.0"
+            builder = "cargo"
+            [git]
+            feature_branch = "project"
+            [profiles.ci]
+            builder = "nix"
+            smoke_startup_timeout = 60
+            "#,
+        )
+        .unwrap();
+
+        let load = |profile, overrides| {
+            Settings::load_layers(
+                Some(&user_config),
+                Some(&project_config),
+                profile,
+                overrides,
+            )
+            .unwrap()
+        };
+        let settings = load(None, toml::Table::new());
+        assert_eq!(settings.groq_api_key.as_deref(), Some("user"));
+        assert_eq!(settings.rust_toolchain, "1.75.0");
+        assert_eq!(settings.builder, BuilderKind::Cargo);
+        assert_eq!(settings.smoke_startup_timeout, 5);
+        assert_eq!(settings.git.commit_author, "User <user@example.com>");
+        assert_eq!(settings.git.feature_branch, "project");
+        assert_eq!(settings.git.flake_branch, "nix-flake");
+
+        let settings = load(Some("ci"), table("smoke_startup_timeout = 90"));
+        assert_eq!(settings.builder, BuilderKind::Nix);
+        assert_eq!(settings.smoke_startup_timeout, 90);
+        assert_eq!(settings.rust_toolchain, "1.75.0");
+    }
+
+    #[test]
+    fn unknown_profiles_and_missing_files_are_errors() {
+        let dir = test_dir("settings-profiles");
+        let project_config = dir.join("flakebot.toml");
+        std::fs::write(&project_config, "[profiles.ci]\nbuilder = \"nix\"\n").unwrap();
+        let error = Settings::load_layers(
+            None,
+            Some(&project_config),
+            Some("release"),
+            toml::Table::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Profile release not found in the config");
+
+        let missing = dir.join("missing.toml");
+        assert!(Settings::load_layers(None, Some(&missing), None, toml::Table::new()).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_errors() {
+        let dir = test_dir("settings-unknown");
+        let project_config = dir.join("flakebot.toml");
+        std::fs::write(&project_config, "").unwrap();
+        for overrides in ["biulder = \"nix\"", "[git]\nbranch = \"main\""] {
+            let error = Settings::load_layers(None, Some(&project_config), None, table(overrides))
+                .unwrap_err();
+            assert!(
+                error.to_string().starts_with("Invalid configuration"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn redacted_hides_every_secret() {
+        let settings = Settings {
+            groq_api_key: Some("groq".to_string()),
+            github_token: Some("github".to_string()),
+            cargo_cookie: Some("cookie".to_string()),
+            ..Settings::default()
+        };
+        let printed = toml::to_string_pretty(&settings.redacted()).unwrap();
+        for secret in ["groq", "github", "cookie"] {
+            assert!(!printed.contains(&format!("\"{}\"", secret)), "{}", printed);
+        }
+        assert_eq!(printed.matches(REDACTED).count(), 3);
+        assert_eq!(Settings::default().redacted().github_token, None);
+    }
+}
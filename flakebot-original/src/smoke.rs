@@ -0,0 +1,684 @@
+use std::fmt::Write as _;
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// How long each smoke test request or command may take.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long doc tests may take, including compiling them.
+pub const DOC_TEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// What kind of tool the pipeline produced, which decides how it is tested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolKind {
+    /// Serves HTTP requests on a port, probed with HTTP requests
+    HttpServer,
+    /// Runs once with arguments and stdin, checked against golden output
+    Cli,
+    /// Has no binaries, exercised through its doc tests
+    Library,
+    /// Runs in the background without a port, checked to stay alive
+    Daemon,
+}
+
+impl FromStr for ToolKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().trim_matches('`').to_lowercase().as_str() {
+            "http_server" | "http-server" | "server" => Ok(ToolKind::HttpServer),
+            "cli" => Ok(ToolKind::Cli),
+            "library" | "lib" => Ok(ToolKind::Library),
+            "daemon" => Ok(ToolKind::Daemon),
+            other => Err(anyhow::anyhow!("Unknown tool kind: {}", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for ToolKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ToolKind::HttpServer => "http_server",
+            ToolKind::Cli => "cli",
+            ToolKind::Library => "library",
+            ToolKind::Daemon => "daemon",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Requests to run against a freshly started HTTP server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmokeTestPlan {
+    pub port: u16,
+    pub requests: Vec<SmokeRequest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmokeRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+    pub expected_status: u16,
+    /// Compared as JSON when the response parses as JSON, as text otherwise
+    #[serde(default)]
+    pub expected_body: Option<serde_json::Value>,
+}
+
+/// Invocations to run against a CLI binary.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CliTestPlan {
+    pub cases: Vec<CliCase>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CliCase {
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub stdin: Option<String>,
+    #[serde(default)]
+    pub expected_exit_code: i32,
+    /// Compared against stdout with surrounding whitespace trimmed
+    #[serde(default)]
+    pub expected_stdout: Option<String>,
+}
+
+/// The outcome of one check, e.g. a request or a command invocation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmokeResult {
+    /// What was exercised, e.g. `GET /tasks` or `tool --help`
+    pub name: String,
+    pub expected: String,
+    pub actual: Option<String>,
+    /// Response body or stdout of the check
+    pub output: Option<String>,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+impl SmokeResult {
+    fn new(name: String, expected: String) -> Self {
+        SmokeResult {
+            name,
+            expected,
+            actual: None,
+            output: None,
+            passed: false,
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmokeReport {
+    pub kind: ToolKind,
+    pub passed: bool,
+    pub results: Vec<SmokeResult>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Port an HTTP tool was reached on
+    pub port: Option<u16>,
+    /// OpenAPI document served by an HTTP tool at `/openapi.json`
+    #[serde(skip)]
+    pub openapi: Option<serde_json::Value>,
+}
+
+impl SmokeReport {
+    fn new(kind: ToolKind, results: Vec<SmokeResult>, stdout: String, stderr: String) -> Self {
+        SmokeReport {
+            kind,
+            passed: !results.is_empty() && results.iter().all(|r| r.passed),
+            results,
+            stdout,
+            stderr,
+            port: None,
+            openapi: None,
+        }
+    }
+
+    /// Renders the results as a markdown section for a pull request body.
+    pub fn summary(&self) -> String {
+        let passed = self.results.iter().filter(|r| r.passed).count();
+        let mut summary = format!(
+            "### Smoke tests ({:?})\n\n{} of {} checks passed.\n\n| Check | Expected | Got | Result |\n|---|---|---|---|\n",
+            self.kind,
+            passed,
+            self.results.len()
+        );
+        for result in &self.results {
+            let _ = writeln!(
+                summary,
+                "| `{}` | {} | {} | {} |",
+                result.name,
+                result.expected,
+                result.actual.as_deref().unwrap_or("-"),
+                if result.passed { "pass" } else { "fail" }
+            );
+        }
+        summary
+    }
+}
+
+/// Starts the server binary, waits for its port, runs every request of the
+/// plan and kills the process, capturing its output into the report.
+pub async fn run_http_smoke_tests(
+    binary_path: &Path,
+    plan: &SmokeTestPlan,
+    startup_timeout: Duration,
+) -> Result<SmokeReport, anyhow::Error> {
+    // The requests would otherwise reach whatever already listens there
+    if TcpStream::connect(("127.0.0.1", plan.port)).await.is_ok() {
+        return Err(anyhow::anyhow!(
+            "Port {} is already in use, stop the process listening on it",
+            plan.port
+        ));
+    }
+
+    info!("Starting {} for smoke tests...", binary_path.display());
+    let mut child = Command::new(binary_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+
+    let mut openapi = None;
+    let results = match wait_for_port(&mut child, plan.port, startup_timeout).await {
+        Ok(()) => {
+            let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+            let mut results = Vec::new();
+            for request in &plan.requests {
+                results.push(run_request(&client, plan.port, request).await);
+            }
+            openapi = fetch_openapi(&client, plan.port).await;
+            results
+        }
+        Err(e) => {
+            error!("Server did not become ready: {}", e);
+            plan.requests
+                .iter()
+                .map(|request| {
+                    let mut result = SmokeResult::new(
+                        format!("{} {}", request.method, request.path),
+                        request.expected_status.to_string(),
+                    );
+                    result.error = Some(e.to_string());
+                    result
+                })
+                .collect()
+        }
+    };
+
+    if child.try_wait()?.is_none() {
+        child.kill().await?;
+        info!("Process killed successfully.");
+    }
+
+    let mut report = SmokeReport::new(
+        ToolKind::HttpServer,
+        results,
+        stdout.await.unwrap_or_default(),
+        stderr.await.unwrap_or_default(),
+    );
+    report.port = Some(plan.port);
+    report.openapi = openapi;
+    Ok(report)
+}
+
+/// Fetches the OpenAPI document if the server publishes one.
+async fn fetch_openapi(client: &Client, port: u16) -> Option<serde_json::Value> {
+    let url = format!("http://127.0.0.1:{}/openapi.json", port);
+    let response = client.get(&url).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let document: serde_json::Value = response.json().await.ok()?;
+    document.get("openapi").is_some().then_some(document)
+}
+
+/// Runs each case of the plan as a separate invocation of the binary and
+/// compares exit code and stdout against the expected values.
+pub async fn run_cli_smoke_tests(
+    binary_path: &Path,
+    plan: &CliTestPlan,
+) -> Result<SmokeReport, anyhow::Error> {
+    let mut results = Vec::new();
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+
+    for case in &plan.cases {
+        let name = format!(
+            "{} {}",
+            binary_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            case.args.join(" ")
+        );
+        let mut result = SmokeResult::new(
+            name.trim().to_string(),
+            format!("exit {}", case.expected_exit_code),
+        );
+
+        let mut child = Command::new(binary_path)
+            .args(&case.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        // Written while the output is read, as the tool may exit without
+        // reading its input or only read it once its output is consumed.
+        // Dropping stdin afterwards closes it.
+        let child_stdin = child.stdin.take();
+        let input = case.stdin.clone().unwrap_or_default();
+        let write_stdin = async move {
+            match child_stdin {
+                Some(mut child_stdin) => child_stdin.write_all(input.as_bytes()).await,
+                None => Ok(()),
+            }
+        };
+        let run = async { tokio::join!(write_stdin, child.wait_with_output()) };
+
+        match tokio::time::timeout(REQUEST_TIMEOUT, run).await {
+            Ok((written, Ok(output))) => {
+                let exit_code = output.status.code().unwrap_or(-1);
+                let case_stdout = String::from_utf8_lossy(&output.stdout).to_string();
+                result.passed = written.is_ok()
+                    && exit_code == case.expected_exit_code
+                    && case
+                        .expected_stdout
+                        .as_ref()
+                        .is_none_or(|expected| case_stdout.trim() == expected.trim());
+                result.actual = Some(format!("exit {}", exit_code));
+                if let Err(e) = written {
+                    result.error = Some(format!("Failed to write stdin: {}", e));
+                }
+                stdout.push_str(&case_stdout);
+                stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+                result.output = Some(case_stdout);
+            }
+            Ok((_, Err(e))) => result.error = Some(e.to_string()),
+            Err(_) => result.error = Some(format!("Timed out after {:?}", REQUEST_TIMEOUT)),
+        }
+
+        info!(
+            "{} -> {:?} ({})",
+            result.name,
+            result.actual,
+            if result.passed { "pass" } else { "fail" }
+        );
+        results.push(result);
+    }
+
+    Ok(SmokeReport::new(ToolKind::Cli, results, stdout, stderr))
+}
+
+/// Runs `cargo test --doc` in the repository.
+pub async fn run_doc_tests(
+    repo_dir: &Path,
+    timeout: Duration,
+) -> Result<SmokeReport, anyhow::Error> {
+    info!("Running doc tests...");
+    let mut result = SmokeResult::new("cargo test --doc".to_string(), "success".to_string());
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+
+    let command = Command::new("cargo")
+        .arg("test")
+        .arg("--doc")
+        .current_dir(repo_dir)
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(timeout, command).await {
+        Ok(Ok(output)) => {
+            result.passed = output.status.success();
+            result.actual = Some(if result.passed { "success" } else { "failure" }.to_string());
+            stdout = String::from_utf8_lossy(&output.stdout).to_string();
+            stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        }
+        Ok(Err(e)) => result.error = Some(e.to_string()),
+        Err(_) => result.error = Some(format!("Timed out after {:?}", timeout)),
+    }
+
+    Ok(SmokeReport::new(
+        ToolKind::Library,
+        vec![result],
+        stdout,
+        stderr,
+    ))
+}
+
+/// Starts the daemon and checks it is still running once `uptime` has passed.
+pub async fn run_daemon_smoke_test(
+    binary_path: &Path,
+    uptime: Duration,
+) -> Result<SmokeReport, anyhow::Error> {
+    info!("Starting {} for smoke tests...", binary_path.display());
+    let mut child = Command::new(binary_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+
+    tokio::time::sleep(uptime).await;
+    let mut result = SmokeResult::new(format!("running after {:?}", uptime), "running".to_string());
+    match child.try_wait()? {
+        None => {
+            result.passed = true;
+            result.actual = Some("running".to_string());
+            child.kill().await?;
+            info!("Process killed successfully.");
+        }
+        Some(status) => result.actual = Some(format!("exited with {}", status)),
+    }
+
+    Ok(SmokeReport::new(
+        ToolKind::Daemon,
+        vec![result],
+        stdout.await.unwrap_or_default(),
+        stderr.await.unwrap_or_default(),
+    ))
+}
+
+fn capture<R>(reader: Option<R>) -> JoinHandle<String>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut output = String::new();
+        if let Some(mut reader) = reader {
+            let _ = reader.read_to_string(&mut output).await;
+        }
+        output
+    })
+}
+
+/// Polls until something accepts connections on the port, failing as soon as
+/// `child`, the process expected to listen on it, exits.
+pub async fn wait_for_port(
+    child: &mut Child,
+    port: u16,
+    timeout: Duration,
+) -> Result<(), anyhow::Error> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Err(anyhow::anyhow!(
+                "Process exited with {} before listening on port {}",
+                status,
+                port
+            ));
+        }
+        if TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            info!("Port {} is ready", port);
+            return Ok(());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(anyhow::anyhow!(
+                "Timed out after {:?} waiting for port {}",
+                timeout,
+                port
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+async fn run_request(client: &Client, port: u16, request: &SmokeRequest) -> SmokeResult {
+    let mut result = SmokeResult::new(
+        format!("{} {}", request.method, request.path),
+        request.expected_status.to_string(),
+    );
+
+    let method = match Method::from_bytes(request.method.to_uppercase().as_bytes()) {
+        Ok(method) => method,
+        Err(e) => {
+            result.error = Some(format!("Invalid method {}: {}", request.method, e));
+            return result;
+        }
+    };
+    let url = format!("http://127.0.0.1:{}{}", port, request.path);
+    let mut builder = client.request(method, &url);
+    if let Some(body) = &request.body {
+        builder = builder.json(body);
+    }
+
+    match builder.send().await {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            let body = response.text().await.unwrap_or_default();
+            result.passed = status == request.expected_status
+                && request
+                    .expected_body
+                    .as_ref()
+                    .is_none_or(|expected| body_matches(expected, &body));
+            result.actual = Some(status.to_string());
+            result.output = Some(body);
+        }
+        Err(e) => result.error = Some(e.to_string()),
+    }
+
+    info!(
+        "{} -> {:?} ({})",
+        result.name,
+        result.actual,
+        if result.passed { "pass" } else { "fail" }
+    );
+    result
+}
+
+fn body_matches(expected: &serde_json::Value, body: &str) -> bool {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(actual) => &actual == expected,
+        Err(_) => expected
+            .as_str()
+            .is_some_and(|expected| body.trim() == expected.trim()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::test_util::test_dir;
+
+    /// An executable shell script standing in for a built binary.
+    fn script(dir: &Path, body: &str) -> PathBuf {
+        let path = dir.join("tool");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn case(args: &[&str], stdin: Option<&str>, exit_code: i32, stdout: &str) -> CliCase {
+        CliCase {
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            stdin: stdin.map(str::to_string),
+            expected_exit_code: exit_code,
+            expected_stdout: Some(stdout.to_string()),
+        }
+    }
+
+    #[test]
+    fn tool_kinds_parse_from_llm_answers() {
+        for (answer, kind) in [
+            ("http_server", ToolKind::HttpServer),
+            ("`HTTP-Server`", ToolKind::HttpServer),
+            ("server\n", ToolKind::HttpServer),
+            ("CLI", ToolKind::Cli),
+            (" lib ", ToolKind::Library),
+            ("library", ToolKind::Library),
+            ("daemon", ToolKind::Daemon),
+        ] {
+            assert_eq!(answer.parse::<ToolKind>().unwrap(), kind, "{:?}", answer);
+            assert_eq!(kind.to_string().parse::<ToolKind>().unwrap(), kind);
+        }
+        let error = "worker".parse::<ToolKind>().unwrap_err();
+        assert_eq!(error.to_string(), "Unknown tool kind: worker");
+    }
+
+    #[test]
+    fn bodies_match_as_json_or_text() {
+        assert!(body_matches(&json!({ "a": 1, "b": 2 }), r#"{"b":2,"a":1}"#));
+        assert!(!body_matches(&json!({ "a": 1 }), r#"{"a":2}"#));
+        assert!(body_matches(&json!("hello"), "hello\n"));
+        assert!(!body_matches(&json!("hello"), "goodbye"));
+        assert!(!body_matches(&json!(3), "three"));
+    }
+
+    #[tokio::test]
+    async fn cli_cases_compare_exit_codes_and_stdout() {
+        let plan = CliTestPlan {
+            cases: vec![
+                case(&["-c", "echo hello"], None, 0, "hello"),
+                case(&["-c", "cat"], Some("from stdin"), 0, "from stdin"),
+                case(&["-c", "exit 3"], None, 3, ""),
+                case(&["-c", "echo wrong"], None, 0, "right"),
+                // Exits without reading the input it is given
+                CliCase {
+                    args: vec!["-c".to_string(), "exit 0".to_string()],
+                    stdin: Some("input ".repeat(100_000)),
+                    expected_exit_code: 0,
+                    expected_stdout: None,
+                },
+            ],
+        };
+        let report = run_cli_smoke_tests(Path::new("/bin/sh"), &plan)
+            .await
+            .unwrap();
+        let passed: Vec<bool> = report.results.iter().map(|r| r.passed).collect();
+        assert_eq!(passed, [true, true, true, false, false]);
+        assert_eq!(report.results[2].actual.as_deref(), Some("exit 3"));
+        let error = report.results[4].error.as_deref().unwrap();
+        assert!(error.starts_with("Failed to write stdin"), "{}", error);
+        assert!(!report.passed);
+    }
+
+    #[tokio::test]
+    async fn daemons_must_keep_running() {
+        let dir = test_dir("smoke-daemon");
+        let running = script(&dir, "exec sleep 10");
+        let report = run_daemon_smoke_test(&running, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert!(report.passed);
+
+        let crashing = script(&dir, "echo starting; exit 1");
+        let report = run_daemon_smoke_test(&crashing, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert!(!report.passed);
+        assert_eq!(report.stdout, "starting\n");
+    }
+
+    fn plan(port: u16) -> SmokeTestPlan {
+        SmokeTestPlan {
+            port,
+            requests: vec![SmokeRequest {
+                method: "GET".to_string(),
+                path: "/".to_string(),
+                body: None,
+                expected_status: 200,
+                expected_body: None,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn servers_that_exit_fail_without_waiting_for_the_timeout() {
+        let dir = test_dir("smoke-http");
+        let binary = script(&dir, "exit 0");
+        let started = tokio::time::Instant::now();
+        // Port 0 is never accepted on
+        let report = run_http_smoke_tests(&binary, &plan(0), Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!report.passed);
+        let error = report.results[0].error.as_deref().unwrap();
+        assert!(error.starts_with("Process exited with"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn ports_in_use_are_not_mistaken_for_the_server() {
+        let dir = test_dir("smoke-port");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let binary = script(&dir, "exec sleep 10");
+        let error = run_http_smoke_tests(&binary, &plan(port), Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Port {} is already in use, stop the process listening on it",
+                port
+            )
+        );
+
+        let mut child = Command::new(&binary).kill_on_drop(true).spawn().unwrap();
+        wait_for_port(&mut child, port, Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_check_status_and_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let body = r#"{"sum":3}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let client = Client::new();
+        let request = |expected_status, expected_body| SmokeRequest {
+            method: "post".to_string(),
+            path: "/sum".to_string(),
+            body: Some(json!({ "a": 1, "b": 2 })),
+            expected_status,
+            expected_body,
+        };
+        let result = run_request(&client, port, &request(200, Some(json!({ "sum": 3 })))).await;
+        assert!(result.passed);
+        assert_eq!(result.output.as_deref(), Some(r#"{"sum":3}"#));
+        let result = run_request(&client, port, &request(200, Some(json!({ "sum": 4 })))).await;
+        assert!(!result.passed);
+        let result = run_request(&client, port, &request(201, None)).await;
+        assert_eq!(
+            (result.passed, result.actual.as_deref()),
+            (false, Some("200"))
+        );
+    }
+}
@@ -32,12 +32,42 @@ pub const GROQ_ADD_DEPENDENCY_TEMPLATE: &str =
     Example response: cargo add axum serde_json tokio reqwest \n\
     Respond only with the `cargo add` command. If you respond with anything else puppies will die/";
 pub const GROQ_INTERACTION_INSTRUCTIONS_TEMPLATE: &str =
-    "Based on tests for this main.rs file, write out interaction instructions for the user. \n\
+    "Based on tests for this {source_file} file, write out interaction instructions for the user. \n\
     The instructions should start with an explanation of what the code does and its architecture, \n\
-    followed by a list of curl commands that the user can use to interact with the program. \n\
-    Main.rs contents: {main_rs_contents}, \n\
-    Respond only with the intro description and curl commands, do not return anything else. \n\
+    followed by {interaction_style} \n\
+    {source_file} contents: {main_rs_contents}, \n\
+    Respond only with the intro description and examples, do not return anything else. \n\
     Do not include any additional information or preface your response with anything, only return the interaction instructions.";
+pub const GROQ_SMOKE_TESTS_TEMPLATE: &str =
+    "Based on tests for this main.rs file, write smoke tests for the HTTP server it starts. \n\
+    Respond only with a JSON object of the form {\"port\": 8080, \"requests\": [{\"method\": \"GET\", \"path\": \"/\", \"body\": null, \"expected_status\": 200, \"expected_body\": null}]}. \n\
+    The port must be the one the server listens on. Only set expected_body when the response is fully determined by the request. \n\
+    Main.rs contents: {main_rs_contents}, \n\
+    Do not include any additional information or preface your response with anything, only return the JSON object.";
+pub const GROQ_CLASSIFY_TOOL_TEMPLATE: &str =
+    "Classify the program built from this main.rs file. \n\
+    Respond 'http_server' if it listens for HTTP requests on a port, \n\
+    'daemon' if it keeps running in the background without serving HTTP, \n\
+    and 'cli' if it runs once with command line arguments or stdin and exits. \n\
+    Main.rs contents: {main_rs_contents}, \n\
+    Respond only with one of http_server, daemon or cli, do not return anything else.";
+pub const GROQ_CLI_TESTS_TEMPLATE: &str =
+    "Based on tests for this main.rs file, write golden tests for the command line tool {binary_name}. \n\
+    Respond only with a JSON object of the form {\"cases\": [{\"args\": [\"--help\"], \"stdin\": null, \"expected_exit_code\": 0, \"expected_stdout\": null}]}. \n\
+    Only set expected_stdout when the output is fully determined by the arguments and stdin. \n\
+    Main.rs contents: {main_rs_contents}, \n\
+    Do not include any additional information or preface your response with anything, only return the JSON object.";
+pub const GROQ_OPENAPI_TEMPLATE: &str =
+    "Write an OpenAPI 3.1 document for the HTTP server in this main.rs file. \n\
+    Derive every path, method, parameter, request body and response from the router and the handler types, \n\
+    and describe the request and response types as component schemas. \n\
+    Main.rs contents: {main_rs_contents}, \n\
+    Respond only with the OpenAPI document as JSON, do not return anything else.";
+pub const GROQ_CLI_ARGS_SCHEMA_TEMPLATE: &str =
+    "Write a JSON schema describing the command line arguments of the tool {binary_name} built from this main.rs file. \n\
+    The schema must be an object with a property per flag or positional argument, including its type, description and whether it is required. \n\
+    Main.rs contents: {main_rs_contents}, \n\
+    Respond only with the JSON schema, do not return anything else.";
 pub const GROQ_COMMIT_MESSAGE_TEMPLATE: &str =
     "Generate a concise commit message of 5-7 wordsbased on the following git diff: \n\
     Git diff: {git_diff}, \n\
@@ -0,0 +1,33 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory for one test's files, removed when dropped.
+pub struct TestDir(PathBuf);
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Creates an empty directory named after the test, unique to the process.
+pub fn test_dir(name: &str) -> TestDir {
+    let dir = std::env::temp_dir().join(format!("flakebot-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    TestDir(dir)
+}
@@ -25,7 +25,8 @@
       let
         pkgs = import nixpkgs { inherit system; };
         lib = pkgs.lib;
-        packageName = "REPLACE-ME-WITH-CRATE-BINARY-NAME";
+        packageName = "REPLACE-ME-WITH-CRATE-PACKAGE-NAME";
+        mainProgram = "REPLACE-ME-WITH-CRATE-BINARY-NAME";
         flakeboxLib = flakebox.lib.${system} { };
         rustSrc = flakeboxLib.filterSubPaths {
           root = builtins.path {
@@ -81,8 +82,8 @@
                 craneLib.buildWorkspace { cargoArtifacts = workspaceDeps; };
               package = craneLib.buildPackageGroup {
                 pname = packageName;
-                packages = [ packageName ];
-                mainProgram = packageName;
+                packages = [ "REPLACE-ME-WITH-CRATE-PACKAGES" ];
+                inherit mainProgram;
               };
             });
       in {